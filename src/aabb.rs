@@ -1,4 +1,4 @@
-use crate::{ray::Ray, vec3::Vec3};
+use crate::vec3::Vec3;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Aabb {
@@ -19,21 +19,52 @@ impl Aabb {
         self.maximum
     }
 
-    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+    // A box that contains nothing, surrounding it with another box yields the other box
+    pub fn empty() -> Self {
+        Self {
+            minimum: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            maximum: Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY),
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.maximum - self.minimum;
+        if extent.x < 0.0 || extent.y < 0.0 || extent.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.maximum - self.minimum;
+        if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Takes the inverse ray direction, as it is shared between all boxes tested for a ray
+    pub fn hit(&self, origin: &Vec3, inv_direction: &Vec3, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
         for a in 0..3 {
-            let inv_d = 1.0 / ray.direction()[a];
-            let mut t0 = (self.minimum[a] - ray.origin()[a]) * inv_d;
-            let mut t1 = (self.maximum[a] - ray.origin()[a]) * inv_d;
+            let inv_d = inv_direction[a];
+            let mut t0 = (self.minimum[a] - origin[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - origin[a]) * inv_d;
 
-            // Swap
             if inv_d < 0.0 {
-                let temp = t0;
-                t0 = t1;
-                t1 = temp;
+                std::mem::swap(&mut t0, &mut t1);
             }
 
-            let t_min = if t0 > t_min { t0 } else { t_min };
-            let t_max = if t1 < t_max { t1 } else { t_max };
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
 
             if t_max <= t_min {
                 return false;
@@ -57,6 +88,15 @@ pub fn bounding_box_sphere(center: Vec3, radius: f64) -> Aabb {
     Aabb::new(center - radius, center + radius)
 }
 
+// Covers a sphere at every position along its path, the centers of the straight segments between
+// them are always inside
+pub fn bounding_box_moving_sphere(centers: &[Vec3], radius: f64) -> Aabb {
+    centers
+        .iter()
+        .map(|center| bounding_box_sphere(*center, radius))
+        .fold(Aabb::empty(), surrounding_box)
+}
+
 pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
     let small = Vec3::new(
         box0.minimum().x.min(box1.minimum().x),
@@ -70,3 +110,44 @@ pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
     );
     Aabb::new(small, big)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box_hit(origin: Vec3, direction: Vec3) -> bool {
+        let unit = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let inv_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        unit.hit(&origin, &inv_direction, 0.001, f64::INFINITY)
+    }
+
+    #[test]
+    fn hits_through_the_box() {
+        assert!(unit_box_hit(
+            Vec3::new(-1.0, 0.5, 0.5),
+            Vec3::new(1.0, 0.0, 0.0)
+        ));
+        assert!(unit_box_hit(
+            Vec3::new(2.0, 2.0, 2.0),
+            Vec3::new(-1.0, -1.0, -1.0)
+        ));
+    }
+
+    #[test]
+    fn slab_intervals_narrow_across_axes() {
+        // Crosses the x slab for t in [0, 1] and the y slab for t in [1.5, 2.5], each on
+        // its own overlaps the ray, together they don't
+        assert!(!unit_box_hit(
+            Vec3::new(0.0, 2.5, 0.5),
+            Vec3::new(1.0, -1.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn misses_behind_the_origin() {
+        assert!(!unit_box_hit(
+            Vec3::new(2.0, 0.5, 0.5),
+            Vec3::new(1.0, 0.0, 0.0)
+        ));
+    }
+}
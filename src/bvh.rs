@@ -1,10 +1,504 @@
-use crate::aabb::Aabb;
+use std::fmt;
 
-pub struct BvhNode {
-    left: Box<BvhNode>,
-    right: Box<BvhNode>,
-    object_index: usize,
+use crate::{
+    aabb::{surrounding_box, Aabb},
+    ray::Ray,
+    vec3::Vec3,
+};
+
+// Relative costs used by the surface area heuristic, tuned on hittable_scene where a
+// node visit is noticeably more expensive than a sphere test
+const TRAVERSAL_COST: f64 = 4.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMethod {
+    // Sort along the longest axis and split the objects in two equal halves
+    Median,
+    // Pick the cheapest of the binned centroid splits according to the surface area heuristic
+    Sah,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhOptions {
+    pub split_method: SplitMethod,
+    pub max_leaf_size: usize,
+    pub bin_count: usize,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self {
+            split_method: SplitMethod::Sah,
+            max_leaf_size: 4,
+            bin_count: 16,
+        }
+    }
+}
+
+// Deepest a tree is allowed to get, so traversal can use a fixed size stack
+const MAX_DEPTH: usize = 64;
+
+// Pointer based tree, only used while building before it is flattened
+struct BvhNode {
+    left: Option<Box<BvhNode>>,
+    right: Option<Box<BvhNode>>,
+    first_object: usize,
+    object_count: usize,
+    split_axis: usize,
     bounding_box: Aabb,
 }
 
-impl BvhNode {}
+impl BvhNode {
+    fn leaf(first_object: usize, object_count: usize, bounding_box: Aabb) -> BvhNode {
+        BvhNode {
+            left: None,
+            right: None,
+            first_object,
+            object_count,
+            split_axis: 0,
+            bounding_box,
+        }
+    }
+}
+
+// Nodes are stored depth first, so the first child of an interior node directly follows it
+struct LinearBvhNode {
+    bounding_box: Aabb,
+    // Leaves: first object in `object_indices`, interior nodes: index of the second child
+    offset: u32,
+    // Zero for interior nodes
+    object_count: u32,
+    split_axis: u8,
+}
+
+pub struct Bvh {
+    nodes: Vec<LinearBvhNode>,
+    // Leaves refer to a range of this list, which in turn holds the object indices
+    object_indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn empty() -> Bvh {
+        Bvh {
+            nodes: vec![],
+            object_indices: vec![],
+        }
+    }
+
+    // Builds a tree over the given bounding boxes, objects are referred to by their index
+    pub fn new(boxes: &[Aabb], options: &BvhOptions) -> Bvh {
+        if boxes.is_empty() {
+            return Self::empty();
+        }
+
+        let mut object_indices: Vec<usize> = (0..boxes.len()).collect();
+        let root = build(boxes, &mut object_indices, 0, 1, options);
+
+        let mut nodes = Vec::with_capacity(2 * boxes.len());
+        flatten(root, &mut nodes);
+        Bvh {
+            nodes,
+            object_indices,
+        }
+    }
+
+    // Returns the index and distance of the closest object hit by the ray.
+    // `hit_object` is asked for the hit distance of a single object within [t_min, t_max].
+    pub fn hit<F>(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        hit_object: &mut F,
+    ) -> Option<(usize, f64)>
+    where
+        F: FnMut(usize, f64, f64) -> Option<f64>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let direction = ray.direction();
+        let inv_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let dir_is_neg = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node
+                .bounding_box
+                .hit(ray.origin(), &inv_direction, t_min, closest_so_far)
+            {
+                if node.object_count > 0 {
+                    let first = node.offset as usize;
+                    let last = first + node.object_count as usize;
+                    for &object_index in &self.object_indices[first..last] {
+                        if let Some(t) = hit_object(object_index, t_min, closest_so_far) {
+                            closest = Some((object_index, t));
+                            closest_so_far = t;
+                        }
+                    }
+                } else {
+                    // Visit the child nearer to the ray origin first, hits there let us skip the other
+                    let (near, far) = if dir_is_neg[node.split_axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far;
+                    stack_size += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+
+        closest
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        if let Some(root) = self.nodes.first() {
+            let root_area = root.bounding_box.surface_area();
+            self.gather_stats(0, 1, root_area, &mut stats);
+            if stats.leaf_count > 0 {
+                stats.average_leaf_size =
+                    self.object_indices.len() as f64 / stats.leaf_count as f64;
+            }
+        }
+        stats
+    }
+
+    fn gather_stats(&self, index: usize, depth: usize, root_area: f64, stats: &mut BvhStats) {
+        let node = &self.nodes[index];
+        let relative_area = if root_area > 0.0 {
+            node.bounding_box.surface_area() / root_area
+        } else {
+            1.0
+        };
+
+        stats.node_count += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+
+        if node.object_count > 0 {
+            stats.leaf_count += 1;
+            stats.sah_cost += relative_area * node.object_count as f64 * INTERSECTION_COST;
+        } else {
+            stats.sah_cost += relative_area * TRAVERSAL_COST;
+            self.gather_stats(index + 1, depth + 1, root_area, stats);
+            self.gather_stats(node.offset as usize, depth + 1, root_area, stats);
+        }
+    }
+}
+
+// Appends the subtree in depth first order and returns the index of its root
+fn flatten(node: BvhNode, nodes: &mut Vec<LinearBvhNode>) -> usize {
+    let index = nodes.len();
+    nodes.push(LinearBvhNode {
+        bounding_box: node.bounding_box,
+        offset: node.first_object as u32,
+        object_count: node.object_count as u32,
+        split_axis: node.split_axis as u8,
+    });
+
+    if let (Some(left), Some(right)) = (node.left, node.right) {
+        flatten(*left, nodes);
+        let second_child = flatten(*right, nodes);
+        nodes[index].offset = second_child as u32;
+        nodes[index].object_count = 0;
+    }
+
+    index
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub average_leaf_size: f64,
+    // Expected cost of a random ray query, roughly the number of node visits and intersections
+    pub sah_cost: f64,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nodes: {}, leaves: {}, depth: {}, average leaf size: {:.2}, sah cost: {:.2}",
+            self.node_count, self.leaf_count, self.max_depth, self.average_leaf_size, self.sah_cost
+        )
+    }
+}
+
+// Builds the subtree for `object_indices`, which start at `first_object` in the full list
+fn build(
+    boxes: &[Aabb],
+    object_indices: &mut [usize],
+    first_object: usize,
+    depth: usize,
+    options: &BvhOptions,
+) -> BvhNode {
+    let count = object_indices.len();
+    let mut bounding_box = Aabb::empty();
+    let mut centroid_bounds = Aabb::empty();
+    for &index in object_indices.iter() {
+        let centroid = boxes[index].centroid();
+        bounding_box = surrounding_box(bounding_box, boxes[index]);
+        centroid_bounds = surrounding_box(centroid_bounds, Aabb::new(centroid, centroid));
+    }
+
+    let max_leaf_size = options.max_leaf_size.max(1);
+    if count <= 1 || depth >= MAX_DEPTH {
+        return BvhNode::leaf(first_object, count, bounding_box);
+    }
+
+    let (mid, split_axis) = match options.split_method {
+        SplitMethod::Median => {
+            if count <= max_leaf_size {
+                return BvhNode::leaf(first_object, count, bounding_box);
+            }
+            median_split(boxes, object_indices, &centroid_bounds)
+        }
+        SplitMethod::Sah => {
+            match sah_split(
+                boxes,
+                object_indices,
+                &bounding_box,
+                &centroid_bounds,
+                options,
+            ) {
+                Some(split) => split,
+                None if count <= max_leaf_size => {
+                    return BvhNode::leaf(first_object, count, bounding_box)
+                }
+                // Splitting is not worth it, but the leaf would be too large
+                None => median_split(boxes, object_indices, &centroid_bounds),
+            }
+        }
+    };
+
+    let (left_indices, right_indices) = object_indices.split_at_mut(mid);
+    let left = build(boxes, left_indices, first_object, depth + 1, options);
+    let right = build(boxes, right_indices, first_object + mid, depth + 1, options);
+
+    BvhNode {
+        left: Some(Box::new(left)),
+        right: Some(Box::new(right)),
+        first_object,
+        object_count: count,
+        split_axis,
+        bounding_box,
+    }
+}
+
+// Sorts the objects along the longest axis and returns the middle together with that axis
+fn median_split(
+    boxes: &[Aabb],
+    object_indices: &mut [usize],
+    centroid_bounds: &Aabb,
+) -> (usize, usize) {
+    let axis = centroid_bounds.longest_axis();
+    object_indices.sort_by(|a, b| {
+        boxes[*a].centroid()[axis]
+            .partial_cmp(&boxes[*b].centroid()[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    (object_indices.len() / 2, axis)
+}
+
+// Partitions the objects by the cheapest binned split and returns where the right half starts
+// together with the split axis, or None when keeping all objects in a leaf is cheaper.
+fn sah_split(
+    boxes: &[Aabb],
+    object_indices: &mut [usize],
+    bounding_box: &Aabb,
+    centroid_bounds: &Aabb,
+    options: &BvhOptions,
+) -> Option<(usize, usize)> {
+    let bin_count = options.bin_count.max(2);
+    let count = object_indices.len();
+    let parent_area = bounding_box.surface_area().max(f64::MIN_POSITIVE);
+
+    let bin_of = |index: usize, axis: usize| -> usize {
+        let min = centroid_bounds.minimum()[axis];
+        let extent = centroid_bounds.maximum()[axis] - min;
+        let offset = (boxes[index].centroid()[axis] - min) / extent;
+        ((offset * bin_count as f64) as usize).min(bin_count - 1)
+    };
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        let extent = centroid_bounds.maximum()[axis] - centroid_bounds.minimum()[axis];
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_boxes = vec![Aabb::empty(); bin_count];
+        let mut bin_counts = vec![0; bin_count];
+        for &index in object_indices.iter() {
+            let bin = bin_of(index, axis);
+            bin_counts[bin] += 1;
+            bin_boxes[bin] = surrounding_box(bin_boxes[bin], boxes[index]);
+        }
+
+        // Sweep from the right to know the cost of everything after each split
+        let mut right_areas = vec![0.0; bin_count];
+        let mut right_counts = vec![0; bin_count];
+        let mut right_box = Aabb::empty();
+        let mut right_count = 0;
+        for bin in (1..bin_count).rev() {
+            right_box = surrounding_box(right_box, bin_boxes[bin]);
+            right_count += bin_counts[bin];
+            right_areas[bin] = right_box.surface_area();
+            right_counts[bin] = right_count;
+        }
+
+        let mut left_box = Aabb::empty();
+        let mut left_count = 0;
+        for split in 1..bin_count {
+            left_box = surrounding_box(left_box, bin_boxes[split - 1]);
+            left_count += bin_counts[split - 1];
+            if left_count == 0 || right_counts[split] == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (left_box.surface_area() * left_count as f64
+                        + right_areas[split] * right_counts[split] as f64)
+                    / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (cost, axis, split) = best?;
+    let leaf_cost = INTERSECTION_COST * count as f64;
+    if cost >= leaf_cost && count <= options.max_leaf_size.max(1) {
+        return None;
+    }
+
+    // Move every object left of the split to the front
+    let mut mid = 0;
+    for i in 0..count {
+        if bin_of(object_indices[i], axis) < split {
+            object_indices.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((mid, axis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sampler::Sampler,
+        scene::Sphere,
+        utils::{random_f64_range, random_in_unit_sphere, random_vec3_range},
+    };
+
+    fn random_spheres(sampler: &mut Sampler) -> Vec<Sphere> {
+        let mut spheres: Vec<Sphere> = (0..200)
+            .map(|_| {
+                let center = random_vec3_range(sampler, -10.0, 10.0);
+                Sphere::new(center, random_f64_range(sampler, 0.1, 1.5), 0)
+            })
+            .collect();
+        // Objects with the same centroid can't be split apart
+        for _ in 0..8 {
+            spheres.push(Sphere::new(glm::dvec3(1.0, 2.0, 3.0), 0.5, 0));
+        }
+        spheres
+    }
+
+    fn brute_force(spheres: &[Sphere], ray: &Ray) -> Option<(usize, f64)> {
+        let mut closest = None;
+        let mut closest_so_far = f64::INFINITY;
+        for (index, sphere) in spheres.iter().enumerate() {
+            if let Some(t) = sphere.hit(ray, 0.001, closest_so_far) {
+                closest = Some((index, t));
+                closest_so_far = t;
+            }
+        }
+        closest
+    }
+
+    fn assert_matches_brute_force(options: &BvhOptions) {
+        let mut sampler = Sampler::from_seed(7);
+        let spheres = random_spheres(&mut sampler);
+        let boxes: Vec<Aabb> = spheres.iter().map(|s| s.bounding_box()).collect();
+        let bvh = Bvh::new(&boxes, options);
+
+        for _ in 0..2000 {
+            let origin = random_vec3_range(&mut sampler, -15.0, 15.0);
+            let ray = Ray::new(origin, glm::normalize(random_in_unit_sphere(&mut sampler)));
+            let hit = bvh.hit(&ray, 0.001, f64::INFINITY, &mut |index, t_min, t_max| {
+                spheres[index].hit(&ray, t_min, t_max)
+            });
+            // Overlapping spheres can be hit at the same distance, compare distances only
+            let expected = brute_force(&spheres, &ray);
+            assert_eq!(hit.map(|(_, t)| t), expected.map(|(_, t)| t));
+        }
+    }
+
+    #[test]
+    fn median_split_finds_the_closest_hit() {
+        for max_leaf_size in [1, 4] {
+            assert_matches_brute_force(&BvhOptions {
+                split_method: SplitMethod::Median,
+                max_leaf_size,
+                ..BvhOptions::default()
+            });
+        }
+    }
+
+    #[test]
+    fn sah_split_finds_the_closest_hit() {
+        for (max_leaf_size, bin_count) in [(1, 2), (4, 16), (8, 32)] {
+            assert_matches_brute_force(&BvhOptions {
+                split_method: SplitMethod::Sah,
+                max_leaf_size,
+                bin_count,
+            });
+        }
+    }
+
+    #[test]
+    fn stats_count_every_object() {
+        let spheres = random_spheres(&mut Sampler::from_seed(7));
+        let boxes: Vec<Aabb> = spheres.iter().map(|s| s.bounding_box()).collect();
+        let bvh = Bvh::new(&boxes, &BvhOptions::default());
+        let mut indices = bvh.object_indices.clone();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..boxes.len()).collect::<Vec<_>>());
+        assert!(bvh.stats().leaf_count > 0);
+    }
+
+    #[test]
+    fn empty_tree_hits_nothing() {
+        let bvh = Bvh::new(&[], &BvhOptions::default());
+        let ray = Ray::new(glm::dvec3(0.0, 0.0, 0.0), glm::dvec3(0.0, 0.0, 1.0));
+        assert!(bvh
+            .hit(&ray, 0.001, f64::INFINITY, &mut |_, _, _| Some(1.0))
+            .is_none());
+    }
+}
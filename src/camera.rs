@@ -1,41 +1,176 @@
-use crate::{ray::Ray, vec3::Vec3};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{
+    ray::Ray,
+    sampler::Sampler,
+    scene::Scene,
+    utils::{random_f64_range, random_in_unit_disk},
+    vec3::Vec3,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // Latitude-longitude panorama covering the full sphere around the camera, with look_at in
+    // the center of the image. Use a 2:1 image for 360 viewers.
+    Equirectangular,
+    // Parallel rays along the view direction, the image covers what the perspective view
+    // shows at the focus distance
+    Orthographic,
+    // Circular fisheye images with v_fov across the circle, the angle from the view direction
+    // grows linearly with the distance from the center (equidistant) or keeps equal solid
+    // angles equally large (equisolid). Pixels outside the circle stay black.
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    // All six 90 degree faces around look_from in a 3x2 atlas, use a 3:2 image. The top row
+    // holds right, left and up, the bottom row down, front and back.
+    CubeMap,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "perspective" => Ok(Projection::Perspective),
+            "equirectangular" | "360" => Ok(Projection::Equirectangular),
+            "orthographic" | "ortho" => Ok(Projection::Orthographic),
+            "fisheye" | "equidistant" => Ok(Projection::FisheyeEquidistant),
+            "equisolid" => Ok(Projection::FisheyeEquisolid),
+            "cubemap" | "cube" => Ok(Projection::CubeMap),
+            _ => Err(format!("unknown projection '{}'", name)),
+        }
+    }
+}
+
+// The names `from_str` takes
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Projection::Perspective => "perspective",
+            Projection::Equirectangular => "equirectangular",
+            Projection::Orthographic => "orthographic",
+            Projection::FisheyeEquidistant => "equidistant",
+            Projection::FisheyeEquisolid => "equisolid",
+            Projection::CubeMap => "cubemap",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A camera from a model file or an existing camera, in the terms of `Camera::setup`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSetup {
+    pub projection: Projection,
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub v_up: Vec3,
+    pub v_fov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
 
 pub struct Camera {
+    projection: Projection,
+
     viewport_width: f64,
     viewport_height: f64,
-    focal_length: f64,
-    _v_fov: f64,
+
+    // What the view is built from, see `setup`
+    look_from: Vec3,
+    look_at: Vec3,
+    v_up: Vec3,
+    v_fov: f64,
+    aperture: f64,
+    focus_dist: f64,
 
     origin: glm::DVec3,
     horizontal: glm::DVec3,
     vertical: glm::DVec3,
     lower_left_corner: glm::DVec3,
 
+    // Camera basis, w points backwards
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    // Thin lens, a radius of zero makes it a pinhole camera
+    lens_radius: f64,
+
+    // Stereo, the eyes sit on the u axis and their views line up at the zero parallax distance
+    interpupillary_distance: f64,
+    zero_parallax_distance: f64,
+
+    // Rays are spread over the time the shutter is open
+    shutter_open: f64,
+    shutter_close: f64,
+    // Where look_from and look_at have moved to when the shutter closes
+    motion: Option<(Vec3, Vec3)>,
+
     ray_directions: Vec<glm::DVec3>,
 }
 
 impl Camera {
     pub fn new() -> Camera {
-        Camera {
+        let mut camera = Camera {
+            projection: Projection::Perspective,
             viewport_width: 2.0,
             viewport_height: 2.0,
-            focal_length: 1.0,
-            _v_fov: 90.0,
+
+            look_from: glm::dvec3(0.0, 0.0, 0.0),
+            look_at: glm::dvec3(0.0, 0.0, -1.0),
+            v_up: glm::dvec3(0.0, 1.0, 0.0),
+            v_fov: 90.0,
+            aperture: 0.0,
+            focus_dist: 1.0,
 
             origin: glm::dvec3(0.0, 0.0, 0.0),
             horizontal: glm::dvec3(0.0, 0.0, 0.0),
             vertical: glm::dvec3(0.0, 0.0, 0.0),
             lower_left_corner: glm::dvec3(0.0, 0.0, 0.0),
 
+            u: glm::dvec3(1.0, 0.0, 0.0),
+            v: glm::dvec3(0.0, 1.0, 0.0),
+            w: glm::dvec3(0.0, 0.0, 1.0),
+            lens_radius: 0.0,
+
+            interpupillary_distance: 0.064,
+            zero_parallax_distance: 1.0,
+
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: None,
+
             ray_directions: vec![],
-        }
+        };
+        camera.on_update();
+        camera
     }
 
     fn on_update(&mut self) {
-        self.lower_left_corner = self.origin
-            - self.horizontal / 2.0
-            - self.vertical / 2.0
-            - glm::dvec3(0.0, 0.0, self.focal_length);
+        let theta = glm::radians(self.v_fov);
+        let h = (theta / 2.0).tan();
+        let viewport_height = 2.0 * h;
+        let aspect_ratio = self.viewport_width / self.viewport_height;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        self.w = glm::normalize(self.look_from - self.look_at);
+        self.u = glm::normalize(glm::cross(self.v_up, self.w));
+        self.v = glm::cross(self.w, self.u);
+
+        self.origin = self.look_from;
+        self.horizontal = self.u * viewport_width * self.focus_dist;
+        self.vertical = self.v * viewport_height * self.focus_dist;
+        self.lower_left_corner =
+            self.origin - self.horizontal / 2.0 - self.vertical / 2.0 - self.w * self.focus_dist;
+        self.lens_radius = self.aperture / 2.0;
     }
 
     pub fn on_resize(&mut self, width: usize, height: usize) {
@@ -44,50 +179,244 @@ impl Camera {
         let aspect_ratio = width as f64 / height as f64;
         self.viewport_height = 2.0;
         self.viewport_width = aspect_ratio * self.viewport_height;
-        self.horizontal = glm::dvec3(self.viewport_width, 0.0, 0.0);
-        self.vertical = glm::dvec3(0.0, self.viewport_height, 0.0);
 
         self.on_update();
     }
 
     fn _move_to(&mut self, x: f64, y: f64) {
-        self.origin = glm::dvec3(x, y, 0.0);
+        let offset = glm::dvec3(x, y, 0.0) - self.look_from;
+        self.look_from = self.look_from + offset;
+        self.look_at = self.look_at + offset;
         self.on_update();
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        Ray::new(
-            self.origin,
-            self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin,
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    // What the camera was last set up with
+    pub fn camera_setup(&self) -> CameraSetup {
+        CameraSetup {
+            projection: self.projection,
+            look_from: self.look_from,
+            look_at: self.look_at,
+            v_up: self.v_up,
+            v_fov: self.v_fov,
+            aperture: self.aperture,
+            focus_dist: self.focus_dist,
+        }
+    }
+
+    pub fn shutter(&self) -> (f64, f64) {
+        (self.shutter_open, self.shutter_close)
+    }
+
+    pub fn motion(&self) -> Option<(Vec3, Vec3)> {
+        self.motion
+    }
+
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    // The camera moves from the pose given to `setup` at shutter open to this one at shutter
+    // close, None keeps it still
+    pub fn set_motion(&mut self, motion: Option<(Vec3, Vec3)>) {
+        self.motion = motion;
+    }
+
+    // The distance between the eyes and where their views line up
+    pub fn stereo(&self) -> (f64, f64) {
+        (self.interpupillary_distance, self.zero_parallax_distance)
+    }
+
+    pub fn set_stereo(&mut self, interpupillary_distance: f64, zero_parallax_distance: f64) {
+        self.interpupillary_distance = interpupillary_distance;
+        self.zero_parallax_distance = zero_parallax_distance;
+    }
+
+    // u and v are in [0, 1], starting in the lower left corner of the image. Returns None
+    // where the projection doesn't cover the image, like the corners of a fisheye.
+    pub fn get_ray(&self, u: f64, v: f64, sampler: &mut Sampler) -> Option<Ray> {
+        let time = random_f64_range(sampler, self.shutter_open, self.shutter_close);
+        self.eye_ray(u, v, 0.0, sampler)
+            .map(|ray| self.move_ray(&ray, time))
+    }
+
+    // Same as `get_ray`, but seen from one of the eyes of a stereo pair
+    pub fn get_eye_ray(&self, u: f64, v: f64, eye: Eye, sampler: &mut Sampler) -> Option<Ray> {
+        let eye_offset = match eye {
+            Eye::Left => -0.5 * self.interpupillary_distance,
+            Eye::Right => 0.5 * self.interpupillary_distance,
+        };
+        let time = random_f64_range(sampler, self.shutter_open, self.shutter_close);
+        self.eye_ray(u, v, eye_offset, sampler)
+            .map(|ray| self.move_ray(&ray, time))
+    }
+
+    // Rays are built for the pose at shutter open, this carries them along with the camera to
+    // where it is at the given time
+    fn move_ray(&self, ray: &Ray, time: f64) -> Ray {
+        let (look_from_end, look_at_end) = match self.motion {
+            Some(motion) if self.shutter_close > self.shutter_open => motion,
+            _ => return Ray::with_time(*ray.origin(), *ray.direction(), time),
+        };
+
+        let s = (time - self.shutter_open) / (self.shutter_close - self.shutter_open);
+        let look_from = self.look_from + (look_from_end - self.look_from) * s;
+        let look_at = self.look_at + (look_at_end - self.look_at) * s;
+        let w = glm::normalize(look_from - look_at);
+        let u = glm::normalize(glm::cross(self.v_up, w));
+        let v = glm::cross(w, u);
+
+        // Same coordinates in the basis of the moved camera
+        let rebase =
+            |p: Vec3| u * glm::dot(p, self.u) + v * glm::dot(p, self.v) + w * glm::dot(p, self.w);
+        Ray::with_time(
+            look_from + rebase(*ray.origin() - self.origin),
+            rebase(*ray.direction()),
+            time,
         )
     }
 
+    // The eye is moved eye_offset along u, zero gives the regular mono view
+    fn eye_ray(&self, u: f64, v: f64, eye_offset: f64, sampler: &mut Sampler) -> Option<Ray> {
+        match self.projection {
+            Projection::Perspective => Some(self.perspective_ray(u, v, eye_offset, sampler)),
+            Projection::Equirectangular => Some(self.equirectangular_ray(u, v, eye_offset)),
+            Projection::Orthographic => Some(self.orthographic_ray(u, v, eye_offset)),
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => self
+                .fisheye_ray(u, v)
+                .map(|ray| self.converge(&ray, self.u * eye_offset)),
+            Projection::CubeMap => {
+                Some(self.converge(&self.cube_map_ray(u, v), self.u * eye_offset))
+            }
+        }
+    }
+
+    // Moves the start of the ray by eye_offset, but keeps it passing through the same point at
+    // the zero parallax distance
+    fn converge(&self, ray: &Ray, eye_offset: Vec3) -> Ray {
+        let target = *ray.origin() + glm::normalize(*ray.direction()) * self.zero_parallax_distance;
+        let origin = *ray.origin() + eye_offset;
+        Ray::new(origin, target - origin)
+    }
+
+    fn perspective_ray(&self, u: f64, v: f64, eye_offset: f64, sampler: &mut Sampler) -> Ray {
+        // Both eyes look through the same window at the zero parallax distance, which gives
+        // off-axis frustums instead of toeing the cameras in
+        let eye = self.origin + self.u * eye_offset;
+        let window = self.origin
+            + (self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin)
+                * (self.zero_parallax_distance / self.focus_dist);
+        let focus_point = eye + (window - eye) * (self.focus_dist / self.zero_parallax_distance);
+
+        // Start on a random point of the lens, everything at the focus distance stays sharp
+        let rd = random_in_unit_disk(sampler) * self.lens_radius;
+        let offset = self.u * rd.x + self.v * rd.y;
+
+        Ray::new(eye + offset, focus_point - eye - offset)
+    }
+
+    fn equirectangular_ray(&self, u: f64, v: f64, eye_offset: f64) -> Ray {
+        // Longitude goes all the way around, latitude from straight down to straight up
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+
+        let direction = self.u * (latitude.cos() * longitude.sin()) + self.v * latitude.sin()
+            - self.w * (latitude.cos() * longitude.cos());
+        let ray = Ray::new(self.origin, direction);
+
+        // Omni-directional stereo, the eyes turn around the origin with the view direction.
+        // The separation fades out towards the poles, where there is no left or right.
+        let right = self.u * longitude.cos() + self.w * longitude.sin();
+        self.converge(&ray, right * (eye_offset * latitude.cos()))
+    }
+
+    fn orthographic_ray(&self, u: f64, v: f64, eye_offset: f64) -> Ray {
+        // Parallel rays never converge, the eyes just see a shifted view
+        let origin = self.origin
+            + self.horizontal * (u - 0.5)
+            + self.vertical * (v - 0.5)
+            + self.u * eye_offset;
+        Ray::new(origin, -self.w)
+    }
+
+    fn fisheye_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        // Image coordinates in [-1, 1] along the shorter side, the circle touches its edges
+        let aspect_ratio = self.viewport_width / self.viewport_height;
+        let (x, y) = if aspect_ratio >= 1.0 {
+            ((2.0 * u - 1.0) * aspect_ratio, 2.0 * v - 1.0)
+        } else {
+            (2.0 * u - 1.0, (2.0 * v - 1.0) / aspect_ratio)
+        };
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        // Angle between the ray and the view direction
+        let half_fov = glm::radians(self.v_fov) / 2.0;
+        let theta = match self.projection {
+            Projection::FisheyeEquisolid => 2.0 * (r * (half_fov / 2.0).sin()).asin(),
+            _ => r * half_fov,
+        };
+        let (cos_phi, sin_phi) = if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+
+        let direction = self.u * (theta.sin() * cos_phi) + self.v * (theta.sin() * sin_phi)
+            - self.w * theta.cos();
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn cube_map_ray(&self, u: f64, v: f64) -> Ray {
+        let column = ((u * 3.0) as usize).min(2);
+        let row = ((v * 2.0) as usize).min(1);
+        // Position on the face in [-1, 1]
+        let x = (u * 3.0 - column as f64) * 2.0 - 1.0;
+        let y = (v * 2.0 - row as f64) * 2.0 - 1.0;
+
+        // Forward, right and up of each face, rows counted from the bottom
+        let (forward, right, up) = match (row, column) {
+            (1, 0) => (self.u, self.w, self.v),
+            (1, 1) => (-self.u, -self.w, self.v),
+            (1, _) => (self.v, self.u, self.w),
+            (_, 0) => (-self.v, self.u, -self.w),
+            (_, 1) => (-self.w, self.u, self.v),
+            _ => (self.w, -self.u, self.v),
+        };
+        Ray::new(self.origin, forward + right * x + up * y)
+    }
+
     pub fn setup(
         &mut self,
         look_from: &Vec3,
         look_at: &Vec3,
         v_up: &Vec3,
         vfov: f64,
-        _aperture: f64,
+        aperture: f64,
         focus_dist: f64,
     ) {
-        self.origin = *look_from;
-
-        let theta = glm::radians(vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h;
-        let aspect_ratio = self.viewport_width as f64 / self.viewport_height as f64;
-        let viewport_width = aspect_ratio * viewport_height;
+        self.look_from = *look_from;
+        self.look_at = *look_at;
+        self.v_up = *v_up;
+        self.v_fov = vfov;
+        self.aperture = aperture;
+        self.focus_dist = focus_dist;
+        self.on_update();
+    }
 
-        let w = glm::normalize(*look_from - *look_at);
-        let u = glm::normalize(glm::cross(*v_up, w));
-        let v = glm::cross(w, u);
+    // Focuses on whatever the ray through the center of the image hits first,
+    // returns the new focus distance or None when the ray hits nothing
+    pub fn autofocus(&mut self, scene: &Scene) -> Option<f64> {
+        let center = self.lower_left_corner + self.horizontal * 0.5 + self.vertical * 0.5;
+        let ray = Ray::with_time(self.origin, center - self.origin, self.shutter_open);
+        let t = scene.intersect(&ray, 0.001, f64::INFINITY)?.t;
 
-        self.origin = *look_from;
-        self.horizontal = u * viewport_width * focus_dist;
-        self.vertical = v * viewport_height * focus_dist;
-        self.lower_left_corner =
-            self.origin - self.horizontal / 2.0 - self.vertical / 2.0 - w * focus_dist;
-        // self.on_update();
+        // The ray reaches the center at t = 1, which is the current focus distance straight ahead
+        let distance = t * self.focus_dist;
+        self.focus_dist = distance;
+        self.on_update();
+        Some(distance)
     }
 }
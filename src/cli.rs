@@ -0,0 +1,170 @@
+// Command line arguments. Settings left out come from the scene file, or the defaults of the
+// built-in scenes.
+
+use std::str::FromStr;
+
+use clap::{value_parser, Args, Parser, Subcommand};
+
+use crate::{
+    camera::Projection,
+    output::ImageFormat,
+    renderer::{Integrator, LightSampling, StereoLayout},
+    tonemap::ToneMapper,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A path tracer, after Ray Tracing in One Weekend")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render an image and save it
+    Render(RenderArgs),
+    /// Print what is in a scene, its BVH and the camera and render settings
+    Info(SceneArgs),
+    /// Time rendering a scene, the image is not saved
+    Bench(BenchArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuiltinScene {
+    // Two metal spheres, looked at from the origin
+    Simple,
+    // The random spheres from the cover of the first book
+    Spheres,
+    // The Cornell box, lit by an area light in the ceiling
+    Cornell,
+}
+
+impl FromStr for BuiltinScene {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "simple" => Ok(BuiltinScene::Simple),
+            "spheres" => Ok(BuiltinScene::Spheres),
+            "cornell" => Ok(BuiltinScene::Cornell),
+            _ => Err(format!("unknown built-in scene '{}'", name)),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SceneArgs {
+    /// TOML scene file, the built-in scene is used without one
+    #[arg(short, long, value_name = "FILE")]
+    pub scene: Option<String>,
+
+    /// Built-in scene: simple, spheres or cornell
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "spheres",
+        conflicts_with = "scene"
+    )]
+    pub builtin: BuiltinScene,
+
+    /// OBJ, PLY, STL or glTF model added to the scene, the first camera in it is used
+    #[arg(short, long, value_name = "FILE")]
+    pub model: Vec<String>,
+
+    /// Seed for the random scene and the samples, the same seed gives the same image
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+
+    /// Image width in pixels, keeps the aspect ratio when the height is left out
+    #[arg(long, value_parser = value_parser!(u32).range(2..))]
+    pub width: Option<u32>,
+
+    /// Image height in pixels, keeps the aspect ratio when the width is left out
+    #[arg(long, value_parser = value_parser!(u32).range(2..))]
+    pub height: Option<u32>,
+
+    /// Camera projection: perspective, equirectangular, orthographic, fisheye, equisolid or
+    /// cubemap
+    #[arg(long, value_name = "NAME")]
+    pub projection: Option<Projection>,
+
+    /// Distance between the eyes of stereo images, in scene units
+    #[arg(long, value_name = "DISTANCE")]
+    pub ipd: Option<f64>,
+
+    /// Distance at which the views of the two eyes line up, in scene units
+    #[arg(long, value_name = "DISTANCE")]
+    pub zero_parallax: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderSettingsArgs {
+    /// Samples per pixel
+    #[arg(short = 'n', long = "spp", value_parser = value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Bounces before a path is cut off
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pub max_depth: Option<u32>,
+
+    /// path (render_recurse) or preview (render, a quick look with a fixed light)
+    #[arg(short, long, value_name = "NAME", default_value = "path")]
+    pub integrator: Integrator,
+
+    /// How the path tracer finds lights: off (only by bouncing into them), direct (sampling
+    /// one at every bounce), or both weighted with the balance or power heuristic
+    #[arg(long, value_name = "NAME", default_value = "power")]
+    pub light_sampling: LightSampling,
+
+    /// Exposure in stops, every stop doubles the brightness
+    #[arg(long, value_name = "STOPS", allow_negative_numbers = true)]
+    pub exposure: Option<f64>,
+
+    /// Tone mapper: clamp (the default), reinhard, reinhard-extended:<white point>, aces or
+    /// uncharted2
+    #[arg(long, value_name = "NAME")]
+    pub tone_mapper: Option<ToneMapper>,
+
+    /// Worker threads, all cores by default
+    #[arg(short = 'j', long, value_parser = value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+
+    /// Render both eyes into one image: side-by-side or top-bottom
+    #[arg(long, value_name = "LAYOUT")]
+    pub stereo: Option<StereoLayout>,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+
+    #[command(flatten)]
+    pub settings: RenderSettingsArgs,
+
+    /// Where to save the image, the format is taken from the extension unless --format is given
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Image format: ppm, png, png16, exr, exr32, pfm or hdr
+    #[arg(long, value_name = "NAME")]
+    pub format: Option<ImageFormat>,
+
+    /// Focus on whatever is in the center of the image
+    #[arg(long)]
+    pub autofocus: bool,
+
+    /// Also write the scene as it was set up to a scene file, to render it again exactly
+    #[arg(long, value_name = "FILE")]
+    pub save_scene: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+
+    // Benchmarks default to 16 samples per pixel instead of the scene's
+    #[command(flatten)]
+    pub settings: RenderSettingsArgs,
+}
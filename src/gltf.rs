@@ -0,0 +1,475 @@
+// glTF 2.0 scenes, .gltf with embedded or external buffers and images, and binary .glb.
+// Meshes are baked into world space, since the scene has no instancing.
+
+use std::{collections::HashMap, sync::Arc};
+
+use ::gltf::{camera::Projection as GltfProjection, image::Format, khr_lights_punctual::Kind};
+
+use crate::{
+    camera::{CameraSetup, Projection},
+    light::PunctualLight,
+    load::LoadError,
+    material::Material,
+    mesh::Mesh,
+    scene::Scene,
+    texture::ImageTexture,
+    vec3::Vec3,
+};
+
+// Column major, like glTF stores them
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+struct Loader<'a> {
+    path: &'a str,
+    buffers: Vec<::gltf::buffer::Data>,
+    images: Vec<::gltf::image::Data>,
+    materials: HashMap<Option<usize>, usize>,
+    textures: HashMap<usize, Arc<ImageTexture>>,
+    cameras: Vec<CameraSetup>,
+}
+
+pub fn load_gltf(path: &str, scene: &mut Scene) -> Result<Vec<CameraSetup>, LoadError> {
+    let (document, buffers, images) =
+        ::gltf::import(path).map_err(|err| LoadError::Gltf(path.to_string(), err))?;
+
+    let mut loader = Loader {
+        path,
+        buffers,
+        images,
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        cameras: vec![],
+    };
+    // Files without a default scene still usually have just the one
+    if let Some(gltf_scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in gltf_scene.nodes() {
+            loader.node(&node, &IDENTITY, scene)?;
+        }
+    }
+
+    Ok(loader.cameras)
+}
+
+impl Loader<'_> {
+    fn node(
+        &mut self,
+        node: &::gltf::Node,
+        parent: &Matrix,
+        scene: &mut Scene,
+    ) -> Result<(), LoadError> {
+        let local = node
+            .transform()
+            .matrix()
+            .map(|column| column.map(f64::from));
+        let world = multiply(parent, &local);
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, &world, scene)?;
+            }
+        }
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, &world);
+        }
+        if let Some(light) = node.light() {
+            scene.punctual_lights.push(punctual_light(&light, &world));
+        }
+
+        for child in node.children() {
+            self.node(&child, &world, scene)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &::gltf::Primitive,
+        world: &Matrix,
+        scene: &mut Scene,
+    ) -> Result<(), LoadError> {
+        // Points and lines have no surface to hit
+        if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+            return Ok(());
+        }
+
+        let buffers = &self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<Vec3> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|p| transform_point(world, to_vec3(p)))
+                .collect(),
+            None => return Ok(()),
+        };
+        let normal_matrix = normal_matrix(world);
+        let normals = reader
+            .read_normals()
+            .map(|normals| {
+                normals
+                    .map(|n| glm::normalize(normal_matrix(to_vec3(n))))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Only the base color texture is used, so its set is the one to read.
+        // glTF puts v = 0 at the top of the image.
+        let tex_coord_set = primitive
+            .material()
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+        let uvs = reader
+            .read_tex_coords(tex_coord_set)
+            .map(|uvs| {
+                uvs.into_f32()
+                    .map(|[u, v]| glm::dvec2(u as f64, 1.0 - v as f64))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Vertex colors are linear already
+        let colors = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgb_f32().map(to_vec3).collect())
+            .unwrap_or_default();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= positions.len())
+        {
+            return Err(LoadError::Content(
+                self.path.to_string(),
+                format!(
+                    "vertex index {} is out of range, there are {}",
+                    index,
+                    positions.len()
+                ),
+            ));
+        }
+        let indices: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        if indices.is_empty() {
+            return Ok(());
+        }
+
+        let material_index = self.material(&primitive.material(), scene)?;
+        scene.add_mesh(Mesh::new(
+            positions,
+            normals,
+            uvs,
+            colors,
+            indices,
+            material_index,
+        ));
+        Ok(())
+    }
+
+    // Only the factors and the base color texture are used, the metallic roughness,
+    // normal and occlusion textures are ignored
+    fn material(
+        &mut self,
+        material: &::gltf::Material,
+        scene: &mut Scene,
+    ) -> Result<usize, LoadError> {
+        if let Some(index) = self.materials.get(&material.index()) {
+            return Ok(*index);
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color_texture = match pbr.base_color_texture() {
+            Some(info) => Some(self.texture(info.texture().source().index())?),
+            None => None,
+        };
+        let emission = to_vec3(material.emissive_factor())
+            * material.emissive_strength().unwrap_or(1.0) as f64;
+
+        let index = scene.add_material(Material::Pbr {
+            base_color: glm::dvec3(r as f64, g as f64, b as f64),
+            base_color_texture,
+            metallic: pbr.metallic_factor() as f64,
+            roughness: pbr.roughness_factor() as f64,
+            emission,
+        });
+        self.materials.insert(material.index(), index);
+        Ok(index)
+    }
+
+    fn texture(&mut self, image_index: usize) -> Result<Arc<ImageTexture>, LoadError> {
+        if let Some(texture) = self.textures.get(&image_index) {
+            return Ok(texture.clone());
+        }
+
+        let image = &self.images[image_index];
+        let channels = match image.format {
+            Format::R8 => 1,
+            Format::R8G8 => 2,
+            Format::R8G8B8 => 3,
+            Format::R8G8B8A8 => 4,
+            format => {
+                return Err(LoadError::Content(
+                    self.path.to_string(),
+                    format!(
+                        "unsupported image format {:?} for a base color texture",
+                        format
+                    ),
+                ))
+            }
+        };
+        let texture = Arc::new(ImageTexture::from_srgb8(
+            image.width as usize,
+            image.height as usize,
+            channels,
+            &image.pixels,
+        ));
+        self.textures.insert(image_index, texture.clone());
+        Ok(texture)
+    }
+
+    // glTF cameras look down their -Z axis with +Y up
+    fn camera(&mut self, camera: &::gltf::Camera, world: &Matrix) {
+        let look_from = transform_point(world, glm::dvec3(0.0, 0.0, 0.0));
+        let forward = glm::normalize(transform_vector(world, glm::dvec3(0.0, 0.0, -1.0)));
+        let v_up = transform_vector(world, glm::dvec3(0.0, 1.0, 0.0));
+
+        // The orthographic view height is 2 tan(v_fov / 2) at the focus distance
+        let (projection, v_fov) = match camera.projection() {
+            GltfProjection::Perspective(perspective) => {
+                (Projection::Perspective, perspective.yfov() as f64)
+            }
+            GltfProjection::Orthographic(orthographic) => (
+                Projection::Orthographic,
+                2.0 * (orthographic.ymag() as f64).atan(),
+            ),
+        };
+        self.cameras.push(CameraSetup {
+            projection,
+            look_from,
+            look_at: look_from + forward,
+            v_up,
+            v_fov: v_fov.to_degrees(),
+            aperture: 0.0,
+            focus_dist: 1.0,
+        });
+    }
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    glm::dvec3(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+// Intensities in candela and lux are used as they are, lights shine down their -z axis.
+// The range is in the light's own units, so it is scaled along with the node.
+fn punctual_light(light: &::gltf::khr_lights_punctual::Light, world: &Matrix) -> PunctualLight {
+    let color = to_vec3(light.color()) * light.intensity() as f64;
+    let range = light.range().map(|range| {
+        let scale = glm::length(transform_vector(world, glm::dvec3(0.0, 0.0, 1.0)));
+        range as f64 * scale
+    });
+    let position = transform_point(world, glm::dvec3(0.0, 0.0, 0.0));
+    let direction = glm::normalize(transform_vector(world, glm::dvec3(0.0, 0.0, -1.0)));
+    match light.kind() {
+        Kind::Point => PunctualLight::Point {
+            position,
+            intensity: color,
+            range,
+        },
+        Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => PunctualLight::Spot {
+            position,
+            direction,
+            intensity: color,
+            range,
+            inner_angle: inner_cone_angle as f64,
+            outer_angle: outer_cone_angle as f64,
+        },
+        Kind::Directional => PunctualLight::Directional {
+            direction,
+            irradiance: color,
+            angular_diameter: 0.0,
+        },
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (column, result_column) in result.iter_mut().enumerate() {
+        for (row, value) in result_column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    result
+}
+
+fn column(m: &Matrix, index: usize) -> Vec3 {
+    glm::dvec3(m[index][0], m[index][1], m[index][2])
+}
+
+fn transform_vector(m: &Matrix, v: Vec3) -> Vec3 {
+    column(m, 0) * v.x + column(m, 1) * v.y + column(m, 2) * v.z
+}
+
+fn transform_point(m: &Matrix, p: Vec3) -> Vec3 {
+    transform_vector(m, p) + column(m, 3)
+}
+
+// Normals go through the inverse transpose, which is the cofactor matrix over the
+// determinant. Only the sign of the determinant matters once they are normalized.
+fn normal_matrix(m: &Matrix) -> impl Fn(Vec3) -> Vec3 {
+    let (c0, c1, c2) = (column(m, 0), column(m, 1), column(m, 2));
+    let sign = glm::dot(c0, glm::cross(c1, c2)).signum();
+    let (n0, n1, n2) = (
+        glm::cross(c1, c2) * sign,
+        glm::cross(c2, c0) * sign,
+        glm::cross(c0, c1) * sign,
+    );
+    move |n: Vec3| n0 * n.x + n1 * n.y + n2 * n.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load::test_file, output::write_png};
+
+    // A mirrored triangle, an orthographic camera and a point light under a translated and
+    // scaled parent. The base color texture reads the second uv set.
+    const NODES: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "translation": [1, 2, 3], "scale": [2, 2, 2], "children": [1, 2, 3] },
+            { "mesh": 0, "scale": [-1, 1, 1] },
+            { "camera": 0, "translation": [0, 0, 5] },
+            { "translation": [0, 1, 0], "extensions": { "KHR_lights_punctual": { "light": 0 } } }
+        ],
+        "meshes": [{
+            "primitives": [{
+                "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2, "TEXCOORD_1": 3 },
+                "material": 0
+            }]
+        }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0, "texCoord": 1 } } }],
+        "textures": [{ "source": 0 }],
+        "images": [{ "uri": "gltf_white.png" }],
+        "cameras": [{
+            "type": "orthographic",
+            "orthographic": { "xmag": 1, "ymag": 1, "znear": 0.1, "zfar": 100 }
+        }],
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {
+            "KHR_lights_punctual": { "lights": [{ "type": "point", "intensity": 5, "range": 10 }] }
+        },
+        "buffers": [{ "uri": "gltf_nodes.bin", "byteLength": 120 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 24 },
+            { "buffer": 0, "byteOffset": 96, "byteLength": 24 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+              "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" },
+            { "bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC2" }
+        ]
+    }"#;
+
+    fn load_nodes() -> (Scene, Vec<CameraSetup>) {
+        let positions: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let normals: [[f32; 3]; 3] = [[0.0, 0.0, 1.0]; 3];
+        // The first uv set, then the second one
+        let uvs: [[f32; 2]; 6] = [
+            [0.0, 0.0],
+            [0.0, 0.0],
+            [0.0, 0.0],
+            [0.25, 0.5],
+            [0.75, 0.5],
+            [0.25, 1.0],
+        ];
+        let floats = positions
+            .iter()
+            .chain(&normals)
+            .flatten()
+            .chain(uvs.iter().flatten());
+        let buffer: Vec<u8> = floats.flat_map(|f| f.to_le_bytes()).collect();
+        test_file("gltf_nodes.bin", buffer);
+        write_png(&test_file("gltf_white.png", []), 1, 1, &[255, 255, 255]).unwrap();
+
+        let mut scene = Scene::empty();
+        let cameras = load_gltf(&test_file("gltf_nodes.gltf", NODES), &mut scene).unwrap();
+        (scene, cameras)
+    }
+
+    #[test]
+    fn node_transforms_compose_down_the_tree() {
+        let (scene, _) = load_nodes();
+        assert_eq!(
+            scene.meshes[0].positions(),
+            [
+                glm::dvec3(1.0, 2.0, 3.0),
+                glm::dvec3(-1.0, 2.0, 3.0),
+                glm::dvec3(1.0, 4.0, 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirrored_nodes_keep_their_normals() {
+        let (scene, _) = load_nodes();
+        for normal in scene.meshes[0].normals() {
+            assert_eq!(*normal, glm::dvec3(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn uvs_come_from_the_set_the_texture_names() {
+        let (scene, _) = load_nodes();
+        assert_eq!(
+            scene.meshes[0].uvs(),
+            [
+                glm::dvec2(0.25, 0.5),
+                glm::dvec2(0.75, 0.5),
+                glm::dvec2(0.25, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn orthographic_ymag_becomes_the_field_of_view() {
+        let (_, cameras) = load_nodes();
+        let camera = cameras[0];
+        assert_eq!(camera.projection, Projection::Orthographic);
+        assert!((camera.v_fov - 90.0).abs() < 1e-9, "{}", camera.v_fov);
+        assert_eq!(camera.look_from, glm::dvec3(1.0, 2.0, 13.0));
+        assert_eq!(camera.look_at, glm::dvec3(1.0, 2.0, 12.0));
+    }
+
+    #[test]
+    fn light_ranges_scale_with_the_node() {
+        let (scene, _) = load_nodes();
+        assert_eq!(
+            scene.punctual_lights,
+            [PunctualLight::Point {
+                position: glm::dvec3(1.0, 4.0, 3.0),
+                intensity: glm::dvec3(5.0, 5.0, 5.0),
+                range: Some(20.0),
+            }]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    sampler::Sampler,
+    utils::random_in_cone,
+    vec3::{Color3, Vec3},
+};
+
+// Lights without a surface. Rays can't hit them, they are only found by sampling them, which
+// both renderers do at every non-specular hit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PunctualLight {
+    // Shines evenly in all directions and falls off with the square of the distance. A
+    // range smoothly fades the light out to nothing at that distance.
+    Point {
+        position: Vec3,
+        intensity: Color3,
+        range: Option<f64>,
+    },
+    // A point light limited to a cone around `direction`. It fades out between the inner and
+    // the outer angle, both in radians from the axis.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Color3,
+        range: Option<f64>,
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+    // Parallel light travelling along `direction`, like the sun. A light with an angular
+    // diameter (in radians) covers a disk of the sky and casts soft shadows.
+    Directional {
+        direction: Vec3,
+        irradiance: Color3,
+        angular_diameter: f64,
+    },
+}
+
+// The light reaching a point from a punctual light
+#[derive(Debug, Clone, Copy)]
+pub struct PunctualSample {
+    // Towards the light
+    pub direction: Vec3,
+    // Infinite for directional lights
+    pub distance: f64,
+    // Irradiance on a surface facing the light, before the cosine
+    pub irradiance: Color3,
+}
+
+impl PunctualLight {
+    pub fn sample(&self, position: &Vec3, sampler: &mut Sampler) -> Option<PunctualSample> {
+        match self {
+            PunctualLight::Point {
+                position: light_position,
+                intensity,
+                range,
+            } => point_sample(position, light_position, *intensity, *range),
+            PunctualLight::Spot {
+                position: light_position,
+                direction,
+                intensity,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                let mut sample = point_sample(position, light_position, *intensity, *range)?;
+                let cos_theta = glm::dot(-sample.direction, glm::normalize(*direction));
+                let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+                // Squared like the falloff glTF suggests, a hard edge when the angles are equal
+                let falloff = if cos_inner - cos_outer > 1e-8 {
+                    ((cos_theta - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0)
+                } else if cos_theta >= cos_outer {
+                    1.0
+                } else {
+                    0.0
+                };
+                if falloff == 0.0 {
+                    return None;
+                }
+                sample.irradiance = sample.irradiance * (falloff * falloff);
+                Some(sample)
+            }
+            PunctualLight::Directional {
+                direction,
+                irradiance,
+                angular_diameter,
+            } => {
+                let towards_light = -glm::normalize(*direction);
+                let direction = if *angular_diameter > 0.0 {
+                    let one_minus_cos = 1.0 - (0.5 * angular_diameter).cos();
+                    random_in_cone(sampler, &towards_light, one_minus_cos)
+                } else {
+                    towards_light
+                };
+                Some(PunctualSample {
+                    direction,
+                    distance: f64::INFINITY,
+                    irradiance: *irradiance,
+                })
+            }
+        }
+    }
+}
+
+fn point_sample(
+    position: &Vec3,
+    light_position: &Vec3,
+    intensity: Color3,
+    range: Option<f64>,
+) -> Option<PunctualSample> {
+    let to_light = *light_position - *position;
+    let distance_squared = glm::dot(to_light, to_light);
+    if distance_squared == 0.0 {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    // The window glTF suggests, close to the inverse square law well inside the range
+    let window = match range {
+        Some(range) if distance >= range => return None,
+        Some(range) => {
+            let window = 1.0 - (distance / range).powi(4);
+            window * window
+        }
+        None => 1.0,
+    };
+    Some(PunctualSample {
+        direction: to_light / distance,
+        distance,
+        irradiance: intensity * (window / distance_squared),
+    })
+}
@@ -0,0 +1,117 @@
+use std::{fmt, io, path::Path};
+
+use crate::{camera::CameraSetup, gltf, obj, ply, scene::Scene, stl};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(String, io::Error),
+    // Malformed file content, lines start at 1
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    // Malformed binary data, at a byte offset into the file
+    Binary {
+        path: String,
+        offset: usize,
+        message: String,
+    },
+    Image(String, image::ImageError),
+    Gltf(String, ::gltf::Error),
+    // Well formed files with content we can't use
+    Content(String, String),
+    UnknownFormat(String),
+}
+
+impl LoadError {
+    pub fn parse(path: &str, line: usize, message: impl Into<String>) -> LoadError {
+        LoadError::Parse {
+            path: path.to_string(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn binary(path: &str, offset: usize, message: impl Into<String>) -> LoadError {
+        LoadError::Binary {
+            path: path.to_string(),
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "{}: {}", path, err),
+            LoadError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path, line, message),
+            LoadError::Binary {
+                path,
+                offset,
+                message,
+            } => write!(f, "{}: byte {}: {}", path, offset, message),
+            LoadError::Image(path, err) => write!(f, "{}: {}", path, err),
+            LoadError::Gltf(path, err) => write!(f, "{}: {}", path, err),
+            LoadError::Content(path, message) => write!(f, "{}: {}", path, message),
+            LoadError::UnknownFormat(path) => {
+                write!(
+                    f,
+                    "cannot tell the model format from the file name '{}'",
+                    path
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Adds the meshes and materials of a model file to the scene, the format is picked from the
+// file extension. The BVH has to be rebuilt afterwards. Returns the cameras found in the file.
+pub fn load_model(path: &str, scene: &mut Scene) -> Result<Vec<CameraSetup>, LoadError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("obj") => obj::load_obj(path, scene).map(|_| vec![]),
+        Some("ply") => ply::load_ply(path, scene).map(|_| vec![]),
+        Some("stl") => stl::load_stl(path, scene).map(|_| vec![]),
+        Some("gltf") | Some("glb") => gltf::load_gltf(path, scene),
+        _ => Err(LoadError::UnknownFormat(path.to_string())),
+    }
+}
+
+// Files referenced by a model are relative to the model itself
+pub fn relative_path(model_path: &str, path: &str) -> String {
+    match Path::new(model_path).parent() {
+        Some(directory) => directory.join(path).to_string_lossy().into_owned(),
+        None => path.to_string(),
+    }
+}
+
+// Writes a file for the loader tests. They all share a directory, so files referring to
+// each other can be written next to each other.
+#[cfg(test)]
+pub fn test_file(name: &str, contents: impl AsRef<[u8]>) -> String {
+    let directory =
+        std::env::temp_dir().join(format!("raytracinginaweekend-tests-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+pub fn error_line(err: LoadError) -> usize {
+    match err {
+        LoadError::Parse { line, .. } => line,
+        err => panic!("expected a parse error, got '{}'", err),
+    }
+}
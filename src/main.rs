@@ -1,94 +1,332 @@
+use std::time::{Duration, Instant};
+
+use bvh::{BvhOptions, SplitMethod};
 use camera::Camera;
+use clap::Parser;
+use cli::{BenchArgs, BuiltinScene, Cli, Command, RenderArgs, RenderSettingsArgs, SceneArgs};
+use output::ImageFormat;
 use renderer::Renderer;
-use scene::{Material, Scene, Sphere};
+use sampler::Sampler;
+use scene::Scene;
+use scene_file::RenderSettings;
+use tonemap::DisplayTransform;
+use vec3::Vec3;
 
+mod aabb;
+mod bvh;
 mod camera;
+mod cli;
+mod gltf;
+mod light;
+mod load;
+mod material;
+mod mesh;
+mod obj;
+mod output;
+mod ply;
 mod ray;
 mod renderer;
+mod sampler;
 mod scene;
+mod scene_file;
+mod stl;
+mod texture;
+mod tonemap;
 mod utils;
 mod vec3;
-mod aabb;
-mod bvh;
+
+// Samples per pixel of `bench` when none are asked for
+const BENCH_SAMPLES: usize = 16;
 
 fn main() {
-    //
-    //  Dielectric - fun next habbajuja
-    //
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Render(args) => render(&args),
+        Command::Info(args) => info(&args),
+        Command::Bench(args) => bench(&args),
+    }
+}
 
-    let ray_tracing_in_one_weekend = false;
-    let mut scene = Scene::new();
+fn render(args: &RenderArgs) {
+    let (scene, mut camera, mut settings) = setup_scene(&args.scene);
+    apply_render_settings(&args.settings, &mut settings);
+    if let Some(output) = &args.output {
+        settings.output = output.clone();
+    }
 
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width = 1200;
-    let image_height = (image_width as f64 / aspect_ratio) as usize;
+    // Checked before rendering, so a typo doesn't cost a whole render
+    let format = match args
+        .format
+        .or_else(|| ImageFormat::from_path(&settings.output))
+    {
+        Some(format) => format,
+        None => fail(format!(
+            "can't tell the image format of {}, give it an extension or pass --format",
+            settings.output
+        )),
+    };
 
-    let mut camera = Camera::new();
-    camera.on_resize(image_width, image_height);
-
-    if ray_tracing_in_one_weekend {
-        scene.spheres = vec![
-            Sphere::new(glm::dvec3(0.0, -100.5, -1.0), 100.0, 0),
-            Sphere::new(glm::dvec3(0.0, 0.0, -1.0), 0.5, 1),
-            Sphere::new(glm::dvec3(-1.0, 0.0, -1.0), 0.5, 2),
-            Sphere::new(glm::dvec3(-1.0, 0.0, -1.0), 0.3, 2),
-            Sphere::new(glm::dvec3(1.0, 0.0, -1.0), 0.5, 3),
-        ];
-        scene.materials = vec![
-            Material {
-                albedo: glm::dvec3(0.8, 0.8, 0.0),
-                roughness: 1.0,
-                metallic: 0.0,
-                ..Default::default()
-            },
-            Material {
-                albedo: glm::dvec3(0.1, 0.2, 0.5),
-                roughness: 1.0,
-                metallic: 0.0,
-                ..Default::default()
-            },
-            Material {
-                albedo: glm::dvec3(0.8, 0.8, 0.8),
-                roughness: 0.0,
-                metallic: 0.0,
-                glass: true,
-                refraction_index: 1.5,
-                ..Default::default()
-            },
-            Material {
-                albedo: glm::dvec3(0.8, 0.6, 0.2),
-                roughness: 0.0,
-                metallic: 0.0,
-                ..Default::default()
-            },
-        ];
-
-        scene = scene::hittable_scene();
-
-        camera.setup(
-            &glm::dvec3(13.0, 2.0, 3.0),
-            &glm::dvec3(0.0, 0.0, 0.0),
-            &glm::dvec3(0.0, 1.0, 0.0),
-            20.0,
-            0.1,
-            10.0,
-        );
+    if args.autofocus {
+        match camera.autofocus(&scene) {
+            Some(distance) => println!("Autofocus at {:.2}", distance),
+            None => println!("Autofocus found nothing, keeping the focus distance"),
+        }
     }
 
-    let mut renderer = Renderer::new();
-    renderer.on_resize(image_width, image_height);
-    let samples_per_pixel = 200;
-    for i in 0..samples_per_pixel {
-        let start = std::time::Instant::now();
-
-        if ray_tracing_in_one_weekend {
-            renderer.render_recurse(&camera, &scene);
-        } else {
-            renderer.render(&camera, &scene);
+    // Writes out the scene as it is set up, random scenes can then be rendered again exactly
+    if let Some(path) = &args.save_scene {
+        if let Err(err) = scene_file::save_scene_file(path, &scene, &camera, &settings) {
+            fail(format!("Failed to save {}: {}", path, err));
         }
+    }
+
+    let mut renderer = setup_renderer(&args.settings, &args.scene, &settings);
+    for i in 0..settings.samples {
+        let start = Instant::now();
+        renderer.render_with(args.settings.integrator, &camera, &scene);
+        println!("Render {}:\t{}ms", i + 1, start.elapsed().as_millis());
+    }
+
+    if let Err(err) = renderer.save_as(&settings.output, format) {
+        fail(format!("Failed to save {}: {}", settings.output, err));
+    }
+}
+
+fn info(args: &SceneArgs) {
+    let (mut scene, camera, settings) = setup_scene(args);
+
+    let triangles: usize = scene.meshes.iter().map(|mesh| mesh.triangle_count()).sum();
+    println!(
+        "Scene       spheres: {}, meshes: {}, triangles: {}, materials: {}, lights: {}, \
+         punctual lights: {}",
+        scene.spheres.len(),
+        scene.meshes.len(),
+        triangles,
+        scene.materials.len(),
+        scene.light_count(),
+        scene.punctual_lights.len()
+    );
+
+    // Compare against a median split to see what the surface area heuristic buys us
+    scene.build_bvh_with(&BvhOptions {
+        split_method: SplitMethod::Median,
+        ..Default::default()
+    });
+    println!("BVH (median) {}", scene.bvh.stats());
+    scene.build_bvh();
+    println!("BVH (sah)    {}", scene.bvh.stats());
+
+    let setup = camera.camera_setup();
+    println!(
+        "Camera      {} from {} at {}, up {}, fov: {}, aperture: {}, focus distance: {}",
+        setup.projection,
+        format_vec3(&setup.look_from),
+        format_vec3(&setup.look_at),
+        format_vec3(&setup.v_up),
+        setup.v_fov,
+        setup.aperture,
+        setup.focus_dist
+    );
+    println!(
+        "Render      {}x{}, {} samples, max depth: {}, exposure: {}, tone mapper: {}, output: {}",
+        settings.width,
+        settings.height,
+        settings.samples,
+        settings.max_depth,
+        settings.exposure,
+        settings.tone_mapper,
+        settings.output
+    );
+}
+
+fn bench(args: &BenchArgs) {
+    let (scene, camera, mut settings) = setup_scene(&args.scene);
+    settings.samples = BENCH_SAMPLES;
+    apply_render_settings(&args.settings, &mut settings);
 
+    let mut renderer = setup_renderer(&args.settings, &args.scene, &settings);
+    let mut total = Duration::ZERO;
+    for i in 0..settings.samples {
+        let start = Instant::now();
+        renderer.render_with(args.settings.integrator, &camera, &scene);
         let elapsed = start.elapsed();
-        println!("Render {}:\t{}ms", i + 1, elapsed.as_millis(),);
+        total += elapsed;
+        println!("Render {}:\t{}ms", i + 1, elapsed.as_millis());
     }
-    renderer.save("image.ppm");
+
+    // Stereo images hold twice the pixels
+    let pixels = renderer.width() * renderer.height();
+    let samples = (pixels * settings.samples) as f64;
+    println!(
+        "{}x{}, {} samples per pixel on {} threads: {:.2}s, {:.1}ms per sample, {:.2}M samples/s",
+        renderer.width(),
+        renderer.height(),
+        settings.samples,
+        renderer.thread_count(),
+        total.as_secs_f64(),
+        total.as_secs_f64() * 1000.0 / settings.samples as f64,
+        samples / total.as_secs_f64() / 1e6
+    );
+}
+
+// The built-in scene or the scene file, with the models added and the image size applied
+fn setup_scene(args: &SceneArgs) -> (Scene, Camera, RenderSettings) {
+    let (mut scene, mut camera, mut settings) = match &args.scene {
+        Some(path) => match scene_file::load_scene_file(path) {
+            Ok(file) => (file.scene, file.camera, file.settings),
+            Err(err) => fail(format!("Failed to load {}", err)),
+        },
+        None => builtin_scene(args.builtin, args.seed),
+    };
+
+    // Models are added to whatever scene was set up above
+    for path in &args.model {
+        match load::load_model(path, &mut scene) {
+            // Look through the first camera of the model, if it has one
+            Ok(cameras) => {
+                if let Some(setup) = cameras.first() {
+                    camera.set_projection(setup.projection);
+                    camera.setup(
+                        &setup.look_from,
+                        &setup.look_at,
+                        &setup.v_up,
+                        setup.v_fov,
+                        setup.aperture,
+                        setup.focus_dist,
+                    );
+                }
+            }
+            Err(err) => fail(format!("Failed to load {}", err)),
+        }
+    }
+    scene.build_bvh();
+
+    // A missing side keeps the aspect ratio. Both are at least 2, `render_recurse` divides by
+    // the size minus one.
+    match (args.width, args.height) {
+        (Some(width), Some(height)) => {
+            settings.width = width as usize;
+            settings.height = height as usize;
+        }
+        (Some(width), None) => {
+            settings.height = (width as usize * settings.height / settings.width).max(2);
+            settings.width = width as usize;
+        }
+        (None, Some(height)) => {
+            settings.width = (height as usize * settings.width / settings.height).max(2);
+            settings.height = height as usize;
+        }
+        (None, None) => {}
+    }
+
+    // Equirectangular panoramas want an aspect ratio of 2:1, cube maps 3:2
+    if let Some(projection) = args.projection {
+        camera.set_projection(projection);
+    }
+    camera.on_resize(settings.width, settings.height);
+
+    // With an equirectangular camera stereo pairs are omni-directional stereo
+    let (mut interpupillary_distance, mut zero_parallax_distance) = camera.stereo();
+    if let Some(ipd) = args.ipd {
+        if ipd.is_nan() || ipd < 0.0 {
+            fail("--ipd must not be negative".to_string());
+        }
+        interpupillary_distance = ipd;
+    }
+    if let Some(distance) = args.zero_parallax {
+        if distance.is_nan() || distance <= 0.0 {
+            fail("--zero-parallax has to be greater than 0".to_string());
+        }
+        zero_parallax_distance = distance;
+    }
+    camera.set_stereo(interpupillary_distance, zero_parallax_distance);
+
+    (scene, camera, settings)
+}
+
+fn builtin_scene(builtin: BuiltinScene, seed: u64) -> (Scene, Camera, RenderSettings) {
+    let mut settings = RenderSettings::default();
+    let mut camera = Camera::new();
+    // Both eyes 6.4cm apart, lined up at the focus distance
+    let scene = match builtin {
+        BuiltinScene::Simple => {
+            camera.set_stereo(0.064, 2.0);
+            Scene::new()
+        }
+        BuiltinScene::Spheres => {
+            camera.setup(
+                &glm::dvec3(13.0, 2.0, 3.0),
+                &glm::dvec3(0.0, 0.0, 0.0),
+                &glm::dvec3(0.0, 1.0, 0.0),
+                20.0,
+                0.1,
+                10.0,
+            );
+            // The diffuse spheres bounce between 0 and 1, open the shutter over that to blur them
+            camera.set_shutter(0.0, 1.0);
+            camera.set_stereo(0.064, 10.0);
+            scene::hittable_scene(&mut Sampler::from_seed(seed))
+        }
+        BuiltinScene::Cornell => {
+            settings.width = 600;
+            settings.height = 600;
+            camera.setup(
+                &glm::dvec3(278.0, 278.0, -800.0),
+                &glm::dvec3(278.0, 278.0, 0.0),
+                &glm::dvec3(0.0, 1.0, 0.0),
+                40.0,
+                0.0,
+                800.0,
+            );
+            // The box is measured in centimeters
+            camera.set_stereo(6.4, 800.0);
+            scene::cornell_box()
+        }
+    };
+    (scene, camera, settings)
+}
+
+fn apply_render_settings(args: &RenderSettingsArgs, settings: &mut RenderSettings) {
+    if let Some(samples) = args.samples {
+        settings.samples = samples as usize;
+    }
+    if let Some(max_depth) = args.max_depth {
+        settings.max_depth = max_depth;
+    }
+    if let Some(exposure) = args.exposure {
+        settings.exposure = exposure;
+    }
+    if let Some(tone_mapper) = args.tone_mapper {
+        settings.tone_mapper = tone_mapper;
+    }
+}
+
+fn setup_renderer(
+    args: &RenderSettingsArgs,
+    scene_args: &SceneArgs,
+    settings: &RenderSettings,
+) -> Renderer {
+    let mut renderer = Renderer::new();
+    renderer.set_stereo_layout(args.stereo);
+    renderer.on_resize(settings.width, settings.height);
+    renderer.set_max_depth(settings.max_depth);
+    renderer.set_light_sampling(args.light_sampling);
+    if let Some(threads) = args.threads {
+        renderer.set_thread_count(threads as usize);
+    }
+    renderer.set_seed(scene_args.seed);
+    renderer.set_display_transform(DisplayTransform {
+        exposure: settings.exposure,
+        tone_mapper: settings.tone_mapper,
+    });
+    renderer
+}
+
+fn format_vec3(v: &Vec3) -> String {
+    format!("({}, {}, {})", v.x, v.y, v.z)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
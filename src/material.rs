@@ -0,0 +1,391 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::{
+    ray::Ray,
+    renderer::HitPayload,
+    sampler::Sampler,
+    texture::{ImageTexture, Texture},
+    utils::{self, near_zero, random_f64, random_in_cone, random_unit_vec3},
+    vec3::{Color3, Vec3},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Material {
+    // Scatters evenly in all directions around the normal
+    Lambertian {
+        albedo: Texture,
+    },
+    // Mirror reflection, blurred by `fuzz` in [0, 1]. Anything blurrier than
+    // MIRROR_ROUGHNESS is glossy rather than specular.
+    Metal {
+        albedo: Color3,
+        fuzz: f64,
+    },
+    // Glass and water, reflects or refracts depending on the angle
+    Dielectric {
+        refraction_index: f64,
+    },
+    // Emits light and does not scatter
+    DiffuseLight {
+        emit: Color3,
+    },
+    // glTF metallic-roughness, a metal or a diffuse base with a clear coat depending on
+    // `metallic`. The base color is multiplied with the texture and the vertex colors.
+    Pbr {
+        base_color: Color3,
+        base_color_texture: Option<Arc<ImageTexture>>,
+        metallic: f64,
+        roughness: f64,
+        emission: Color3,
+    },
+}
+
+// Metal fuzz and Pbr roughness below this reflect like a perfect mirror
+const MIRROR_ROUGHNESS: f64 = 1e-3;
+
+// A bounce picked by `Material::scatter`
+pub struct ScatterRecord {
+    pub attenuation: Color3,
+    pub ray: Ray,
+    // Mirror bounces, `evaluate` and `pdf` leave them out. Sampled lights can't light them,
+    // so lights they hit are counted by the bounce alone.
+    pub specular: bool,
+}
+
+impl Material {
+    // Samples a bounce, returns None when the ray is absorbed. The attenuation is what the
+    // material reflects into the direction over the density it was picked with.
+    pub fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitPayload,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
+        match self {
+            Material::Lambertian { albedo } => {
+                let mut scatter_direction = rec.world_normal + random_unit_vec3(sampler);
+
+                // Catch degenerate scatter direction
+                if near_zero(&scatter_direction) {
+                    scatter_direction = rec.world_normal;
+                }
+
+                Some(ScatterRecord {
+                    attenuation: albedo.value(rec),
+                    ray: Ray::with_time(rec.world_position, scatter_direction, r_in.time()),
+                    specular: false,
+                })
+            }
+            Material::Metal { albedo, fuzz } => {
+                let scattered = Ray::with_time(
+                    rec.world_position,
+                    glossy_direction(r_in, rec, *fuzz, sampler),
+                    r_in.time(),
+                );
+
+                if glm::dot(*scattered.direction(), rec.world_normal) > 0.0 {
+                    Some(ScatterRecord {
+                        attenuation: *albedo,
+                        ray: scattered,
+                        specular: *fuzz < MIRROR_ROUGHNESS,
+                    })
+                } else {
+                    None
+                }
+            }
+            Material::Dielectric { refraction_index } => {
+                let refraction_ratio = if rec.front_face {
+                    1.0 / refraction_index
+                } else {
+                    *refraction_index
+                };
+
+                let unit_direction = glm::normalize(*r_in.direction());
+                let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+                let cannot_refract = refraction_ratio * sin_theta > 1.0;
+                let direction = if cannot_refract
+                    || (utils::reflectance(cos_theta, refraction_ratio) > random_f64(sampler))
+                {
+                    glm::reflect(unit_direction, rec.world_normal)
+                } else {
+                    glm::refract(unit_direction, rec.world_normal, refraction_ratio)
+                };
+
+                Some(ScatterRecord {
+                    attenuation: glm::dvec3(1.0, 1.0, 1.0),
+                    ray: Ray::with_time(rec.world_position, direction, r_in.time()),
+                    specular: true,
+                })
+            }
+            Material::DiffuseLight { .. } => None,
+            Material::Pbr {
+                metallic,
+                roughness,
+                ..
+            } => {
+                let unit_direction = glm::normalize(*r_in.direction());
+                let mirror = *roughness < MIRROR_ROUGHNESS;
+
+                // Metals tint their reflections, the coat on everything else reflects like
+                // plastic with an index of 1.5 and lets the rest through to the diffuse base
+                let (attenuation, direction, specular) = if random_f64(sampler) < *metallic {
+                    let direction = glossy_direction(r_in, rec, *roughness, sampler);
+                    (self.albedo(rec), direction, mirror)
+                } else {
+                    let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
+                    if utils::reflectance(cos_theta, 1.5) > random_f64(sampler) {
+                        let direction = glossy_direction(r_in, rec, *roughness, sampler);
+                        (glm::dvec3(1.0, 1.0, 1.0), direction, mirror)
+                    } else {
+                        let mut direction = rec.world_normal + random_unit_vec3(sampler);
+                        if near_zero(&direction) {
+                            direction = rec.world_normal;
+                        }
+                        (self.albedo(rec), direction, false)
+                    }
+                };
+
+                if glm::dot(direction, rec.world_normal) > 0.0 {
+                    Some(ScatterRecord {
+                        attenuation,
+                        ray: Ray::with_time(rec.world_position, direction, r_in.time()),
+                        specular,
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // How much of the light coming from `direction` the non-specular part reflects towards
+    // r_in, the cosine included. For directions not picked by `scatter`, like those to lights.
+    pub fn evaluate(&self, r_in: &Ray, rec: &HitPayload, direction: &Vec3) -> Color3 {
+        let cosine = glm::dot(rec.world_normal, glm::normalize(*direction));
+        if cosine <= 0.0 {
+            return glm::dvec3(0.0, 0.0, 0.0);
+        }
+        // The glossy lobes reflect their color over the density they are sampled with
+        self.albedo(rec) * (self.diffuse_probability(r_in, rec) * cosine / PI)
+            + self.glossy_reflectance(r_in, rec)
+                * glossy_pdf(r_in, rec, direction, self.roughness())
+    }
+
+    // Solid angle density `scatter` picks a non-specular bounce into `direction` with
+    pub fn pdf(&self, r_in: &Ray, rec: &HitPayload, direction: &Vec3) -> f64 {
+        let cosine = glm::dot(rec.world_normal, glm::normalize(*direction));
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        self.diffuse_probability(r_in, rec) * cosine / PI
+            + self.glossy_probability(r_in, rec)
+                * glossy_pdf(r_in, rec, direction, self.roughness())
+    }
+
+    // How often `scatter` picks the cosine weighted diffuse bounce
+    fn diffuse_probability(&self, r_in: &Ray, rec: &HitPayload) -> f64 {
+        match self {
+            Material::Lambertian { .. } => 1.0,
+            // What the metal and the coat let through to the base
+            Material::Pbr { metallic, .. } => {
+                let unit_direction = glm::normalize(*r_in.direction());
+                let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
+                (1.0 - metallic) * (1.0 - utils::reflectance(cos_theta, 1.5))
+            }
+            _ => 0.0,
+        }
+    }
+
+    // How often `scatter` picks a glossy reflection
+    fn glossy_probability(&self, r_in: &Ray, rec: &HitPayload) -> f64 {
+        match self {
+            Material::Metal { .. } => 1.0,
+            Material::Pbr { metallic, .. } => {
+                let unit_direction = glm::normalize(*r_in.direction());
+                let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
+                metallic + (1.0 - metallic) * utils::reflectance(cos_theta, 1.5)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // The attenuations of the glossy reflections, weighted by how often they are picked
+    fn glossy_reflectance(&self, r_in: &Ray, rec: &HitPayload) -> Color3 {
+        match self {
+            Material::Metal { albedo, .. } => *albedo,
+            // The metal is tinted, the coat is not
+            Material::Pbr { metallic, .. } => {
+                let unit_direction = glm::normalize(*r_in.direction());
+                let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
+                let coat = (1.0 - metallic) * utils::reflectance(cos_theta, 1.5);
+                self.albedo(rec) * *metallic + glm::dvec3(coat, coat, coat)
+            }
+            _ => glm::dvec3(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn emitted(&self) -> Color3 {
+        match self {
+            Material::DiffuseLight { emit } => *emit,
+            Material::Pbr { emission, .. } => *emission,
+            _ => glm::dvec3(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Base color at the hit point, the preview renderer shades with it too
+    pub fn albedo(&self, rec: &HitPayload) -> Color3 {
+        match self {
+            Material::Lambertian { albedo } => albedo.value(rec),
+            Material::Metal { albedo, .. } => *albedo,
+            Material::Dielectric { .. } => glm::dvec3(1.0, 1.0, 1.0),
+            Material::DiffuseLight { emit } => *emit,
+            Material::Pbr {
+                base_color,
+                base_color_texture,
+                ..
+            } => {
+                let texture = base_color_texture
+                    .as_ref()
+                    .map_or(glm::dvec3(1.0, 1.0, 1.0), |texture| texture.sample(&rec.uv));
+                *base_color * texture * rec.color
+            }
+        }
+    }
+
+    // How much reflections are blurred, used by the preview renderer
+    pub fn roughness(&self) -> f64 {
+        match self {
+            Material::Lambertian { .. } | Material::DiffuseLight { .. } => 1.0,
+            Material::Metal { fuzz, .. } => *fuzz,
+            Material::Dielectric { .. } => 0.0,
+            Material::Pbr { roughness, .. } => *roughness,
+        }
+    }
+}
+
+// Glossy reflections spread evenly over a cone around the mirror direction. It reaches as
+// far as the mirror direction plus a point in a ball of radius `roughness` does.
+fn glossy_cone(roughness: f64) -> f64 {
+    let roughness = roughness.min(1.0);
+    1.0 - (1.0 - roughness * roughness).sqrt()
+}
+
+fn glossy_direction(r_in: &Ray, rec: &HitPayload, roughness: f64, sampler: &mut Sampler) -> Vec3 {
+    let reflected = glm::reflect(glm::normalize(*r_in.direction()), rec.world_normal);
+    if roughness < MIRROR_ROUGHNESS {
+        reflected
+    } else {
+        random_in_cone(sampler, &reflected, glossy_cone(roughness))
+    }
+}
+
+// Density of `glossy_direction` picking `direction`, 0 for mirrors which only pick the one
+fn glossy_pdf(r_in: &Ray, rec: &HitPayload, direction: &Vec3, roughness: f64) -> f64 {
+    if roughness < MIRROR_ROUGHNESS {
+        return 0.0;
+    }
+    let reflected = glm::reflect(glm::normalize(*r_in.direction()), rec.world_normal);
+    let one_minus_cos = glossy_cone(roughness);
+    if 1.0 - glm::dot(reflected, glm::normalize(*direction)) <= one_minus_cos {
+        1.0 / (2.0 * PI * one_minus_cos)
+    } else {
+        0.0
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::Lambertian {
+            albedo: Texture::Solid(glm::dvec3(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Straight down onto the xy plane
+    fn hit() -> (Ray, HitPayload) {
+        let ray = Ray::new(glm::dvec3(0.0, 0.0, 1.0), glm::dvec3(0.0, 0.0, -1.0));
+        let rec = HitPayload {
+            world_normal: glm::dvec3(0.0, 0.0, 1.0),
+            front_face: true,
+            ..HitPayload::default()
+        };
+        (ray, rec)
+    }
+
+    // Integrates the density over the sphere of directions
+    fn pdf_integral(material: &Material) -> f64 {
+        let (ray, rec) = hit();
+        let mut sampler = Sampler::from_seed(5);
+        let count = 200_000;
+        let sum: f64 = (0..count)
+            .map(|_| material.pdf(&ray, &rec, &random_unit_vec3(&mut sampler)))
+            .sum();
+        4.0 * PI * sum / count as f64
+    }
+
+    #[test]
+    fn non_specular_densities_integrate_to_one() {
+        let materials = [
+            Material::default(),
+            Material::Metal {
+                albedo: glm::dvec3(0.9, 0.9, 0.9),
+                fuzz: 0.8,
+            },
+            Material::Pbr {
+                base_color: glm::dvec3(0.5, 0.5, 0.5),
+                base_color_texture: None,
+                metallic: 0.4,
+                roughness: 0.7,
+                emission: glm::dvec3(0.0, 0.0, 0.0),
+            },
+        ];
+        for material in &materials {
+            let integral = pdf_integral(material);
+            assert!(
+                (integral - 1.0).abs() < 0.02,
+                "{:?}: {}",
+                material,
+                integral
+            );
+        }
+    }
+
+    #[test]
+    fn glossy_bounces_are_weighed_by_their_density() {
+        let (ray, rec) = hit();
+        let material = Material::Metal {
+            albedo: glm::dvec3(0.9, 0.6, 0.3),
+            fuzz: 0.3,
+        };
+        let mut sampler = Sampler::from_seed(6);
+        for _ in 0..1000 {
+            let scattered = material.scatter(&ray, &rec, &mut sampler).unwrap();
+            assert!(!scattered.specular);
+            let direction = scattered.ray.direction();
+            let ratio =
+                material.evaluate(&ray, &rec, direction) / material.pdf(&ray, &rec, direction);
+            assert!(glm::length(ratio - scattered.attenuation) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn mirrors_stay_specular() {
+        let (ray, rec) = hit();
+        let material = Material::Metal {
+            albedo: glm::dvec3(0.9, 0.9, 0.9),
+            fuzz: 0.0,
+        };
+        let scattered = material
+            .scatter(&ray, &rec, &mut Sampler::from_seed(7))
+            .unwrap();
+        assert!(scattered.specular);
+        assert_eq!(material.pdf(&ray, &rec, scattered.ray.direction()), 0.0);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{
+    aabb::Aabb,
+    ray::Ray,
+    sampler::Sampler,
+    utils::random_f64,
+    vec3::{Color3, Vec3},
+};
+
+// Indexed triangle mesh, normals, uvs and colors are either empty or hold one entry per position
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<glm::DVec2>,
+    colors: Vec<Color3>,
+    indices: Vec<[u32; 3]>,
+    material_index: usize,
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<glm::DVec2>,
+        colors: Vec<Color3>,
+        indices: Vec<[u32; 3]>,
+        material_index: usize,
+    ) -> Mesh {
+        Mesh {
+            positions,
+            normals,
+            uvs,
+            colors,
+            indices,
+            material_index,
+        }
+    }
+
+    // A parallelogram spanned by the edges u and v from a corner, facing along u x v. The edges
+    // must not be parallel.
+    pub fn quad(corner: Vec3, u: Vec3, v: Vec3, material_index: usize) -> Mesh {
+        let normal = glm::normalize(glm::cross(u, v));
+        Mesh::new(
+            vec![corner, corner + u, corner + u + v, corner + v],
+            vec![normal; 4],
+            vec![
+                glm::dvec2(0.0, 0.0),
+                glm::dvec2(1.0, 0.0),
+                glm::dvec2(1.0, 1.0),
+                glm::dvec2(0.0, 1.0),
+            ],
+            vec![],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material_index,
+        )
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn material_index(&self) -> usize {
+        self.material_index
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[glm::DVec2] {
+        &self.uvs
+    }
+
+    pub fn colors(&self) -> &[Color3] {
+        &self.colors
+    }
+
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    fn vertices(&self, triangle: usize) -> (Vec3, Vec3, Vec3) {
+        let [i0, i1, i2] = self.indices[triangle];
+        (
+            self.positions[i0 as usize],
+            self.positions[i1 as usize],
+            self.positions[i2 as usize],
+        )
+    }
+
+    // Axis aligned triangles would have a flat box the slab test never hits, so it is padded
+    pub fn bounding_box(&self, triangle: usize) -> Aabb {
+        let (p0, p1, p2) = self.vertices(triangle);
+        let padding = glm::dvec3(0.0001, 0.0001, 0.0001);
+        Aabb::new(
+            glm::min(glm::min(p0, p1), p2) - padding,
+            glm::max(glm::max(p0, p1), p2) + padding,
+        )
+    }
+
+    // Watertight ray/triangle intersection (Woo, Benthin and Wald 2013), rays through a shared
+    // edge or vertex always hit one of the triangles. Returns the distance and the barycentric
+    // coordinates of the second and third vertex.
+    pub fn hit(
+        &self,
+        triangle: usize,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        let direction = *ray.direction();
+        let (p0, p1, p2) = self.vertices(triangle);
+
+        // Shear the triangle into a space where the ray goes along +z from the origin
+        let kz = max_dimension(&glm::abs(direction));
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if direction[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        let sx = direction[kx] / direction[kz];
+        let sy = direction[ky] / direction[kz];
+        let sz = 1.0 / direction[kz];
+
+        let a = p0 - *ray.origin();
+        let b = p1 - *ray.origin();
+        let c = p2 - *ray.origin();
+        let ax = a[kx] - sx * a[kz];
+        let ay = a[ky] - sy * a[kz];
+        let bx = b[kx] - sx * b[kz];
+        let by = b[ky] - sy * b[kz];
+        let cx = c[kx] - sx * c[kz];
+        let cy = c[ky] - sy * c[kz];
+
+        // Scaled barycentric coordinates, the ray misses when their signs differ
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        Some((t, v / det, w / det))
+    }
+
+    pub fn area(&self, triangle: usize) -> f64 {
+        let (p0, p1, p2) = self.vertices(triangle);
+        0.5 * glm::length(glm::cross(p1 - p0, p2 - p0))
+    }
+
+    // Points are spread evenly over the triangle
+    pub fn sample_point(&self, triangle: usize, sampler: &mut Sampler) -> Vec3 {
+        let (p0, p1, p2) = self.vertices(triangle);
+        let su = random_f64(sampler).sqrt();
+        let v = random_f64(sampler);
+        p0 * (1.0 - su) + p1 * (su * (1.0 - v)) + p2 * (su * v)
+    }
+
+    pub fn geometric_normal(&self, triangle: usize) -> Vec3 {
+        let (p0, p1, p2) = self.vertices(triangle);
+        glm::normalize(glm::cross(p1 - p0, p2 - p0))
+    }
+
+    // Interpolated vertex normal, the face normal for meshes without normals
+    pub fn shading_normal(&self, triangle: usize, b1: f64, b2: f64) -> Vec3 {
+        if self.normals.is_empty() {
+            return self.geometric_normal(triangle);
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        glm::normalize(
+            self.normals[i0 as usize] * (1.0 - b1 - b2)
+                + self.normals[i1 as usize] * b1
+                + self.normals[i2 as usize] * b2,
+        )
+    }
+
+    pub fn uv(&self, triangle: usize, b1: f64, b2: f64) -> glm::DVec2 {
+        if self.uvs.is_empty() {
+            return glm::dvec2(b1, b2);
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        self.uvs[i0 as usize] * (1.0 - b1 - b2)
+            + self.uvs[i1 as usize] * b1
+            + self.uvs[i2 as usize] * b2
+    }
+
+    // Interpolated vertex color, white for meshes without colors
+    pub fn color(&self, triangle: usize, b1: f64, b2: f64) -> Color3 {
+        if self.colors.is_empty() {
+            return glm::dvec3(1.0, 1.0, 1.0);
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        self.colors[i0 as usize] * (1.0 - b1 - b2)
+            + self.colors[i1 as usize] * b1
+            + self.colors[i2 as usize] * b2
+    }
+}
+
+fn max_dimension(v: &Vec3) -> usize {
+    if v.x > v.y && v.x > v.z {
+        0
+    } else if v.y > v.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sampler::Sampler,
+        utils::{random_f64, random_vec3_range},
+    };
+
+    // Six triangles around a shared center vertex, tilted so no edge lines up with an axis
+    fn fan() -> Mesh {
+        let center = glm::dvec3(0.3, -0.2, 0.1);
+        let (u, v) = (glm::dvec3(1.0, 0.2, 0.3), glm::dvec3(-0.1, 0.9, 0.4));
+        let mut positions = vec![center];
+        for k in 0..6 {
+            let angle = k as f64 * std::f64::consts::PI / 3.0;
+            positions.push(center + u * angle.cos() + v * angle.sin());
+        }
+        let indices = (0..6u32).map(|k| [0, k + 1, (k + 1) % 6 + 1]).collect();
+        Mesh::new(positions, vec![], vec![], vec![], indices, 0)
+    }
+
+    fn hits(mesh: &Mesh, ray: &Ray) -> bool {
+        (0..mesh.triangle_count())
+            .any(|triangle| mesh.hit(triangle, ray, 0.0, f64::INFINITY).is_some())
+    }
+
+    #[test]
+    fn rays_through_shared_edges_and_vertices_hit() {
+        let mesh = fan();
+        let center = mesh.positions[0];
+        let mut sampler = Sampler::from_seed(11);
+        for _ in 0..1000 {
+            let origin = random_vec3_range(&mut sampler, -5.0, 5.0) + glm::dvec3(0.0, 0.0, 10.0);
+            // The shared vertex, and points along the edges between neighbouring triangles
+            assert!(hits(&mesh, &Ray::new(origin, center - origin)));
+            for outer in &mesh.positions[1..] {
+                let on_edge = center + (*outer - center) * random_f64(&mut sampler);
+                assert!(hits(&mesh, &Ray::new(origin, on_edge - origin)));
+            }
+        }
+    }
+
+    #[test]
+    fn rays_through_the_quad_diagonal_hit() {
+        let (corner, u, v) = (
+            glm::dvec3(-1.0, -1.0, 0.0),
+            glm::dvec3(2.0, 0.0, 0.5),
+            glm::dvec3(0.0, 2.0, 0.0),
+        );
+        let positions = vec![corner, corner + u, corner + u + v, corner + v];
+        let mesh = Mesh::new(
+            positions,
+            vec![],
+            vec![],
+            vec![],
+            vec![[0, 1, 2], [0, 2, 3]],
+            0,
+        );
+        let (corner, opposite) = (mesh.positions[0], mesh.positions[2]);
+        let mut sampler = Sampler::from_seed(12);
+        for _ in 0..1000 {
+            let origin = random_vec3_range(&mut sampler, -5.0, 5.0) + glm::dvec3(0.0, 0.0, 10.0);
+            let on_diagonal = corner + (opposite - corner) * random_f64(&mut sampler);
+            assert!(hits(&mesh, &Ray::new(origin, on_diagonal - origin)));
+        }
+    }
+
+    #[test]
+    fn rays_past_the_outer_edge_miss() {
+        let mesh = fan();
+        let center = mesh.positions[0];
+        let outside = center + (mesh.positions[1] - center) * 1.01;
+        let origin = glm::dvec3(0.0, 0.0, 10.0);
+        assert!(!hits(&mesh, &Ray::new(origin, outside - origin)));
+    }
+}
@@ -0,0 +1,494 @@
+// Wavefront OBJ models and their MTL material libraries
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    sync::Arc,
+};
+
+use crate::{
+    load::{relative_path, LoadError},
+    material::Material,
+    mesh::Mesh,
+    scene::Scene,
+    texture::{ImageTexture, Texture},
+    vec3::{Color3, Vec3},
+};
+
+// Every object, group and material change starts a new mesh, since meshes have one material
+pub fn load_obj(path: &str, scene: &mut Scene) -> Result<(), LoadError> {
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut texcoords: Vec<glm::DVec2> = vec![];
+
+    let mut materials: HashMap<String, usize> = HashMap::new();
+    let mut textures: HashMap<String, Arc<ImageTexture>> = HashMap::new();
+    let mut default_material = None;
+    let mut builder = MeshBuilder::default();
+
+    for (line_index, line) in read_lines(path)?.iter().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| LoadError::parse(path, line_number, message);
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&arguments).map_err(error)?),
+            "vn" => normals.push(parse_vec3(&arguments).map_err(error)?),
+            "vt" => {
+                let u = parse_number(arguments.first().copied()).map_err(error)?;
+                // The v coordinate is optional for 1D textures
+                let v = match arguments.get(1) {
+                    Some(v) => parse_number(Some(v)).map_err(error)?,
+                    None => 0.0,
+                };
+                texcoords.push(glm::dvec2(u, v));
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(error("a face needs at least 3 vertices".to_string()));
+                }
+                let material_index = match builder.material_index {
+                    Some(index) => index,
+                    None => *default_material
+                        .get_or_insert_with(|| scene.add_material(Material::default())),
+                };
+                builder.material_index = Some(material_index);
+
+                let mut corners = Vec::with_capacity(arguments.len());
+                for argument in &arguments {
+                    let corner =
+                        parse_corner(argument, positions.len(), texcoords.len(), normals.len())
+                            .map_err(error)?;
+                    corners.push(builder.vertex(corner, &positions, &texcoords, &normals));
+                }
+
+                // Polygons are split into a fan around the first vertex
+                for i in 1..corners.len() - 1 {
+                    builder
+                        .indices
+                        .push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "o" | "g" => {
+                let material_index = builder.material_index;
+                builder.finish(scene);
+                builder.material_index = material_index;
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                let material_index = *materials
+                    .get(&name)
+                    .ok_or_else(|| error(format!("unknown material '{}'", name)))?;
+                builder.finish(scene);
+                builder.material_index = Some(material_index);
+            }
+            "mtllib" => {
+                // Several libraries can be listed, but most files only use one
+                for library in &arguments {
+                    let library_path = relative_path(path, library);
+                    load_mtl(&library_path, scene, &mut materials, &mut textures)?;
+                }
+            }
+            // Smoothing groups, lines, points and free form geometry are ignored
+            _ => {}
+        }
+    }
+
+    builder.finish(scene);
+    Ok(())
+}
+
+fn read_lines(path: &str) -> Result<Vec<String>, LoadError> {
+    let file = File::open(path).map_err(|err| LoadError::Io(path.to_string(), err))?;
+    BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .map_err(|err| LoadError::Io(path.to_string(), err))
+}
+
+fn parse_number(token: Option<&str>) -> Result<f64, String> {
+    let token = token.ok_or_else(|| "missing number".to_string())?;
+    token
+        .parse()
+        .map_err(|_| format!("expected a number, found '{}'", token))
+}
+
+fn parse_vec3(arguments: &[&str]) -> Result<Vec3, String> {
+    Ok(glm::dvec3(
+        parse_number(arguments.first().copied())?,
+        parse_number(arguments.get(1).copied())?,
+        parse_number(arguments.get(2).copied())?,
+    ))
+}
+
+// Indices start at 1, negative ones count back from the last element read so far
+fn parse_index(token: &str, count: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("expected a {} index, found '{}'", what, token))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} is out of range, there are {}",
+            what, index, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+// Position, texcoord and normal index of a face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// One of v, v/vt, v//vn or v/vt/vn
+fn parse_corner(
+    token: &str,
+    position_count: usize,
+    texcoord_count: usize,
+    normal_count: usize,
+) -> Result<Corner, String> {
+    let mut parts = token.split('/');
+    let position = parse_index(parts.next().unwrap_or(""), position_count, "vertex")?;
+    let texcoord = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(parse_index(part, texcoord_count, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(parse_index(part, normal_count, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex '{}'", token));
+    }
+    Ok((position, texcoord, normal))
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<glm::DVec2>,
+    indices: Vec<[u32; 3]>,
+    material_index: Option<usize>,
+
+    // OBJ indexes positions, texcoords and normals separately, meshes share one index
+    vertices: HashMap<Corner, u32>,
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn vertex(
+        &mut self,
+        corner: Corner,
+        positions: &[Vec3],
+        texcoords: &[glm::DVec2],
+        normals: &[Vec3],
+    ) -> u32 {
+        if let Some(index) = self.vertices.get(&corner) {
+            return *index;
+        }
+
+        let (position, texcoord, normal) = corner;
+        self.positions.push(positions[position]);
+        match normal {
+            Some(normal) => self.normals.push(normals[normal]),
+            None => self.missing_normals = true,
+        }
+        match texcoord {
+            Some(texcoord) => self.uvs.push(texcoords[texcoord]),
+            None => self.missing_uvs = true,
+        }
+
+        let index = self.positions.len() as u32 - 1;
+        self.vertices.insert(corner, index);
+        index
+    }
+
+    // Adds the mesh built so far to the scene and starts over
+    fn finish(&mut self, scene: &mut Scene) {
+        let builder = std::mem::take(self);
+        if builder.indices.is_empty() {
+            return;
+        }
+
+        // Normals and uvs have to be there for every vertex or not at all
+        let normals = if builder.missing_normals {
+            vec![]
+        } else {
+            builder.normals
+        };
+        let uvs = if builder.missing_uvs {
+            vec![]
+        } else {
+            builder.uvs
+        };
+        scene.add_mesh(Mesh::new(
+            builder.positions,
+            normals,
+            uvs,
+            vec![],
+            builder.indices,
+            builder.material_index.unwrap_or_default(),
+        ));
+    }
+}
+
+// The subset of the MTL statements that maps onto our materials
+struct MtlMaterial {
+    diffuse: Color3,
+    // Unset is different from black, illum 3 falls back to the diffuse color
+    specular: Option<Color3>,
+    emission: Color3,
+    shininess: f64,
+    refraction_index: f64,
+    dissolve: f64,
+    illumination: u32,
+    diffuse_map: Option<String>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: glm::dvec3(0.8, 0.8, 0.8),
+            specular: None,
+            emission: glm::dvec3(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            refraction_index: 1.5,
+            dissolve: 1.0,
+            illumination: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+fn load_mtl(
+    path: &str,
+    scene: &mut Scene,
+    materials: &mut HashMap<String, usize>,
+    textures: &mut HashMap<String, Arc<ImageTexture>>,
+) -> Result<(), LoadError> {
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_index, line) in read_lines(path)?.iter().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| LoadError::parse(path, line_number, message);
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                let material = convert_material(path, &material, textures)?;
+                materials.insert(name, scene.add_material(material));
+            }
+            current = Some((arguments.join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => return Err(error(format!("'{}' before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_vec3(&arguments).map_err(error)?,
+            "Ks" => material.specular = Some(parse_vec3(&arguments).map_err(error)?),
+            "Ke" => material.emission = parse_vec3(&arguments).map_err(error)?,
+            "Ns" => material.shininess = parse_number(arguments.first().copied()).map_err(error)?,
+            "Ni" => {
+                material.refraction_index =
+                    parse_number(arguments.first().copied()).map_err(error)?
+            }
+            "d" => material.dissolve = parse_number(arguments.first().copied()).map_err(error)?,
+            "Tr" => {
+                material.dissolve = 1.0 - parse_number(arguments.first().copied()).map_err(error)?
+            }
+            "illum" => {
+                material.illumination =
+                    parse_number(arguments.first().copied()).map_err(error)? as u32
+            }
+            "map_Kd" => {
+                // Options like -s or -o come first, the file name is last
+                let file = arguments
+                    .last()
+                    .ok_or_else(|| error("missing texture file name".to_string()))?;
+                material.diffuse_map = Some(relative_path(path, file));
+            }
+            // Ambient color, other maps and the rest have nothing to map onto
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        let material = convert_material(path, &material, textures)?;
+        materials.insert(name, scene.add_material(material));
+    }
+    Ok(())
+}
+
+// Picks the closest of our materials: anything emissive becomes a light, see-through
+// materials glass and illum 3 mirrors metal, with the shininess turned into fuzz. A bright Ks
+// without illum 3 is only a highlight, so it stays a diffuse material.
+fn convert_material(
+    path: &str,
+    material: &MtlMaterial,
+    textures: &mut HashMap<String, Arc<ImageTexture>>,
+) -> Result<Material, LoadError> {
+    let max_component = |color: &Color3| color.x.max(color.y).max(color.z);
+
+    if max_component(&material.emission) > 0.0 {
+        return Ok(Material::DiffuseLight {
+            emit: material.emission,
+        });
+    }
+    if material.dissolve < 1.0 || matches!(material.illumination, 4 | 6 | 7 | 9) {
+        return Ok(Material::Dielectric {
+            refraction_index: material.refraction_index,
+        });
+    }
+    if material.illumination == 3 {
+        return Ok(Material::Metal {
+            albedo: material.specular.unwrap_or(material.diffuse),
+            fuzz: (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt(),
+        });
+    }
+
+    let albedo = match &material.diffuse_map {
+        Some(file) => {
+            let texture = match textures.get(file) {
+                Some(texture) => texture.clone(),
+                None => {
+                    let texture = Arc::new(ImageTexture::load(file).map_err(|err| {
+                        LoadError::Image(format!("{} (from {})", file, path), err)
+                    })?);
+                    textures.insert(file.clone(), texture.clone());
+                    texture
+                }
+            };
+            Texture::Image(texture, material.diffuse)
+        }
+        None => Texture::Solid(material.diffuse),
+    };
+    Ok(Material::Lambertian { albedo })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{error_line, test_file};
+
+    fn load(name: &str, contents: &str) -> Result<Scene, LoadError> {
+        let mut scene = Scene::empty();
+        load_obj(&test_file(name, contents), &mut scene)?;
+        Ok(scene)
+    }
+
+    #[test]
+    fn loads_polygons_as_triangle_fans() {
+        let scene = load(
+            "quad.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 -1//1\n",
+        )
+        .unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].triangle_count(), 2);
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let err = load("number.obj", "# comment\n\nv 0 0 0\nv 0 x 0\n")
+            .err()
+            .unwrap();
+        assert_eq!(error_line(err), 4);
+
+        let err = load("index.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\nf 1 2 4\n")
+            .err()
+            .unwrap();
+        assert_eq!(error_line(err), 5);
+
+        let err = load("face.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n").err().unwrap();
+        assert_eq!(error_line(err), 3);
+
+        let err = load("material.obj", "v 0 0 0\n\nusemtl missing\n")
+            .err()
+            .unwrap();
+        assert_eq!(error_line(err), 3);
+    }
+
+    #[test]
+    fn mtl_errors_point_at_the_line_in_the_library() {
+        test_file("broken.mtl", "newmtl red\nKd 1 0 0\n\nKs 1 zero 0\n");
+        let err = load("broken.obj", "mtllib broken.mtl\nv 0 0 0\n")
+            .err()
+            .unwrap();
+        match err {
+            LoadError::Parse { path, line, .. } => {
+                assert!(path.ends_with("broken.mtl"));
+                assert_eq!(line, 4);
+            }
+            err => panic!("expected a parse error, got '{}'", err),
+        }
+    }
+
+    #[test]
+    fn illum_3_without_ks_reflects_the_diffuse_color() {
+        let material = MtlMaterial {
+            diffuse: glm::dvec3(0.9, 0.6, 0.2),
+            illumination: 3,
+            ..MtlMaterial::default()
+        };
+        match convert_material("test.mtl", &material, &mut HashMap::new()).unwrap() {
+            Material::Metal { albedo, .. } => assert_eq!(albedo, glm::dvec3(0.9, 0.6, 0.2)),
+            material => panic!("expected a metal, got {:?}", material),
+        }
+    }
+
+    #[test]
+    fn bright_specular_keeps_the_diffuse_map() {
+        let texture = Arc::new(ImageTexture::from_srgb8(1, 1, 3, &[255, 255, 255]));
+        let mut textures = HashMap::from([("white.png".to_string(), texture)]);
+        let material = MtlMaterial {
+            diffuse: glm::dvec3(0.2, 0.2, 0.2),
+            specular: Some(glm::dvec3(0.9, 0.9, 0.9)),
+            diffuse_map: Some("white.png".to_string()),
+            ..MtlMaterial::default()
+        };
+        match convert_material("test.mtl", &material, &mut textures).unwrap() {
+            Material::Lambertian {
+                albedo: Texture::Image(..),
+            } => {}
+            material => panic!("expected a textured lambertian, got {:?}", material),
+        }
+    }
+
+    #[test]
+    fn diffuse_maps_are_tinted_by_kd() {
+        let texture = Arc::new(ImageTexture::from_srgb8(1, 1, 3, &[255, 255, 255]));
+        let mut textures = HashMap::from([("white.png".to_string(), texture)]);
+        let material = MtlMaterial {
+            diffuse: glm::dvec3(0.5, 0.25, 1.0),
+            diffuse_map: Some("white.png".to_string()),
+            ..MtlMaterial::default()
+        };
+        match convert_material("test.mtl", &material, &mut textures).unwrap() {
+            Material::Lambertian {
+                albedo: Texture::Image(_, tint),
+            } => assert_eq!(tint, glm::dvec3(0.5, 0.25, 1.0)),
+            material => panic!("expected a textured lambertian, got {:?}", material),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write as _};
+use std::path::Path;
+use std::str::FromStr;
+
+use exr::prelude::f16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    // ASCII P3 portable pixmap
+    Ppm,
+    // 8 bit sRGB
+    Png,
+    // 16 bit sRGB, for when banding in 8 bits becomes visible
+    Png16,
+
+    // High dynamic range formats store the linear radiance, without any display transform
+    // OpenEXR with half floats
+    Exr,
+    // OpenEXR with 32 bit floats
+    Exr32,
+    // Portable float map
+    Pfm,
+    // Radiance rgbe
+    Hdr,
+}
+
+impl ImageFormat {
+    // Picks the format from the file extension, 16 bit png has to be asked for explicitly
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            "png16" => Ok(ImageFormat::Png16),
+            "exr" => Ok(ImageFormat::Exr),
+            "exr32" => Ok(ImageFormat::Exr32),
+            "pfm" => Ok(ImageFormat::Pfm),
+            "hdr" => Ok(ImageFormat::Hdr),
+            _ => Err(format!("unknown image format '{}'", name)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Png(png::EncodingError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{}", err),
+            SaveError::Png(err) => write!(f, "png encoding failed: {}", err),
+            SaveError::Exr(err) => write!(f, "exr encoding failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for SaveError {
+    fn from(err: png::EncodingError) -> Self {
+        SaveError::Png(err)
+    }
+}
+
+impl From<exr::error::Error> for SaveError {
+    fn from(err: exr::error::Error) -> Self {
+        SaveError::Exr(err)
+    }
+}
+
+// All writers take tightly packed rgb rows, starting with the top row
+
+pub fn write_ppm(path: &str, width: usize, height: usize, rgb: &[u8]) -> Result<(), SaveError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P3\n{} {}\n255\n", width, height)?;
+    for pixel in rgb.chunks_exact(3) {
+        writeln!(file, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+pub fn write_png(path: &str, width: usize, height: usize, rgb: &[u8]) -> Result<(), SaveError> {
+    let mut writer = png_writer(path, width, height, png::BitDepth::Eight)?;
+    writer.write_image_data(rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_png16(path: &str, width: usize, height: usize, rgb: &[u16]) -> Result<(), SaveError> {
+    // Png stores 16 bit samples big endian
+    let bytes: Vec<u8> = rgb.iter().flat_map(|value| value.to_be_bytes()).collect();
+    let mut writer = png_writer(path, width, height, png::BitDepth::Sixteen)?;
+    writer.write_image_data(&bytes)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_exr(
+    path: &str,
+    width: usize,
+    height: usize,
+    rgb: &[f32],
+    half: bool,
+) -> Result<(), SaveError> {
+    let pixel = |x: usize, y: usize| {
+        let index = 3 * (x + y * width);
+        (rgb[index], rgb[index + 1], rgb[index + 2])
+    };
+
+    if half {
+        exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let (r, g, b) = pixel(x, y);
+            (f16::from_f32(r), f16::from_f32(g), f16::from_f32(b))
+        })?;
+    } else {
+        exr::prelude::write_rgb_file(path, width, height, pixel)?;
+    }
+    Ok(())
+}
+
+pub fn write_pfm(path: &str, width: usize, height: usize, rgb: &[f32]) -> Result<(), SaveError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    // A negative scale means little endian
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+
+    // Pfm stores the bottom row first
+    for row in rgb.chunks_exact(3 * width).rev() {
+        for value in row {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+pub fn write_hdr(path: &str, width: usize, height: usize, rgb: &[f32]) -> Result<(), SaveError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(
+        file,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut scanline = Vec::with_capacity(width);
+    for row in rgb.chunks_exact(3 * width) {
+        scanline.clear();
+        scanline.extend(row.chunks_exact(3).map(|c| to_rgbe(c[0], c[1], c[2])));
+        write_hdr_scanline(&mut file, &scanline)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+// Shared exponent encoding, each channel keeps 8 bits of mantissa
+fn to_rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    let max = r.max(g).max(b);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // The exponent byte tops out at 2^127, anything brighter, infinity included, is
+    // written as the largest value there is
+    if max >= 2f32.powi(127) {
+        return [255, 255, 255, 255];
+    }
+
+    // max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+// Writes a scanline with the run length encoding readers expect, which stores each
+// component separately and only exists for widths in [8, 32767]
+fn write_hdr_scanline(file: &mut impl io::Write, scanline: &[[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if !(8..=32767).contains(&width) {
+        for pixel in scanline {
+            file.write_all(pixel)?;
+        }
+        return Ok(());
+    }
+
+    file.write_all(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8])?;
+    let mut component = Vec::with_capacity(width);
+    for c in 0..4 {
+        component.clear();
+        component.extend(scanline.iter().map(|pixel| pixel[c]));
+
+        let mut i = 0;
+        while i < width {
+            // Runs are only worth it from a length of 3
+            let mut run = 1;
+            while i + run < width && run < 127 && component[i + run] == component[i] {
+                run += 1;
+            }
+            if run >= 3 {
+                file.write_all(&[128 + run as u8, component[i]])?;
+                i += run;
+                continue;
+            }
+
+            // Copy literally until the next run starts
+            let start = i;
+            while i < width && i - start < 128 {
+                if i + 2 < width
+                    && component[i] == component[i + 1]
+                    && component[i] == component[i + 2]
+                {
+                    break;
+                }
+                i += 1;
+            }
+            file.write_all(&[(i - start) as u8])?;
+            file.write_all(&component[start..i])?;
+        }
+    }
+    Ok(())
+}
+
+fn png_writer(
+    path: &str,
+    width: usize,
+    height: usize,
+    depth: png::BitDepth,
+) -> Result<png::Writer<BufWriter<File>>, SaveError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(depth);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    Ok(encoder.write_header()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_keeps_the_brightest_channel() {
+        assert_eq!(to_rgbe(1.0, 0.5, 0.0), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(0.0, 0.0, 0.0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rgbe_saturates_huge_and_infinite_values() {
+        assert_eq!(to_rgbe(f32::INFINITY, 0.0, 0.0), [255, 255, 255, 255]);
+        assert_eq!(to_rgbe(f32::MAX, 1.0, 1.0), [255, 255, 255, 255]);
+        assert_eq!(to_rgbe(f32::NAN, 1.0, 0.0), to_rgbe(0.0, 1.0, 0.0));
+    }
+}
@@ -0,0 +1,486 @@
+// Stanford PLY meshes, in ASCII or binary with either byte order
+
+use std::{fs, str::SplitWhitespace};
+
+use crate::{
+    load::LoadError,
+    material::Material,
+    mesh::Mesh,
+    scene::Scene,
+    texture::Texture,
+    tonemap::srgb_eotf,
+    vec3::{Color3, Vec3},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::I8),
+            "uchar" | "uint8" => Some(ScalarType::U8),
+            "short" | "int16" => Some(ScalarType::I16),
+            "ushort" | "uint16" => Some(ScalarType::U16),
+            "int" | "int32" => Some(ScalarType::I32),
+            "uint" | "uint32" => Some(ScalarType::U32),
+            "float" | "float32" => Some(ScalarType::F32),
+            "double" | "float64" => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyType {
+    Scalar(ScalarType),
+    // Type of the length, then of the items
+    List(ScalarType, ScalarType),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PropertyType)>,
+}
+
+pub fn load_ply(path: &str, scene: &mut Scene) -> Result<(), LoadError> {
+    let data = fs::read(path).map_err(|err| LoadError::Io(path.to_string(), err))?;
+    let (format, elements, header_lines, body_start) = parse_header(path, &data)?;
+    let mut reader = Reader::new(path, &data, format, header_lines, body_start)?;
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut colors: Vec<Color3> = vec![];
+    let mut indices: Vec<[u32; 3]> = vec![];
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(
+                &mut reader,
+                element,
+                &mut positions,
+                &mut normals,
+                &mut colors,
+            )?,
+            "face" => read_faces(&mut reader, element, positions.len(), &mut indices)?,
+            // Edges, materials and whatever else is skipped over
+            _ => {
+                for _ in 0..element.count {
+                    for (_, property) in &element.properties {
+                        reader.skip(*property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(LoadError::parse(
+            path,
+            1,
+            "no faces, point clouds can't be rendered as a mesh",
+        ));
+    }
+
+    let material = if colors.is_empty() {
+        Material::default()
+    } else {
+        Material::Lambertian {
+            albedo: Texture::VertexColor,
+        }
+    };
+    let material_index = scene.add_material(material);
+    scene.add_mesh(Mesh::new(
+        positions,
+        normals,
+        vec![],
+        colors,
+        indices,
+        material_index,
+    ));
+    Ok(())
+}
+
+// Returns the format, the elements, the number of header lines and where the data starts
+fn parse_header(
+    path: &str,
+    data: &[u8],
+) -> Result<(Format, Vec<Element>, usize, usize), LoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut offset = 0;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let end = match data[offset..].iter().position(|byte| *byte == b'\n') {
+            Some(end) => offset + end,
+            None => return Err(LoadError::parse(path, line_number, "missing end_header")),
+        };
+        let line = String::from_utf8_lossy(&data[offset..end]).into_owned();
+        offset = end + 1;
+        let error = |message: String| LoadError::parse(path, line_number, message);
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if tokens.first() != Some(&"ply") {
+                return Err(error("not a ply file".to_string()));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error(format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(format!("expected an element count, found '{}'", count)))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let count_type = parse_scalar_type(count_type).map_err(error)?;
+                let item_type = parse_scalar_type(item_type).map_err(error)?;
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element".to_string()))?;
+                element
+                    .properties
+                    .push((name.to_string(), PropertyType::List(count_type, item_type)));
+            }
+            ["property", scalar_type, name] => {
+                let scalar_type = parse_scalar_type(scalar_type).map_err(error)?;
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element".to_string()))?;
+                element
+                    .properties
+                    .push((name.to_string(), PropertyType::Scalar(scalar_type)));
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(format!("malformed header line '{}'", line.trim()))),
+        }
+    }
+
+    let format = format.ok_or_else(|| LoadError::parse(path, line_number, "missing format"))?;
+    Ok((format, elements, line_number, offset))
+}
+
+fn parse_scalar_type(name: &str) -> Result<ScalarType, String> {
+    ScalarType::parse(name).ok_or_else(|| format!("unknown property type '{}'", name))
+}
+
+fn read_vertices(
+    reader: &mut Reader,
+    element: &Element,
+    positions: &mut Vec<Vec3>,
+    normals: &mut Vec<Vec3>,
+    colors: &mut Vec<Color3>,
+) -> Result<(), LoadError> {
+    let find = |names: &[&str]| {
+        element
+            .properties
+            .iter()
+            .position(|(name, _)| names.contains(&name.as_str()))
+    };
+    let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+    let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let color = [
+        find(&["red", "r", "diffuse_red"]),
+        find(&["green", "g", "diffuse_green"]),
+        find(&["blue", "b", "diffuse_blue"]),
+    ];
+    if position.contains(&None) {
+        return Err(reader.error("vertices need x, y and z"));
+    }
+    let has_normals = !normal.contains(&None);
+    let has_colors = !color.contains(&None);
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, (_, property)) in values.iter_mut().zip(&element.properties) {
+            match property {
+                PropertyType::Scalar(scalar_type) => *value = reader.read(*scalar_type)?,
+                PropertyType::List(..) => reader.skip(*property)?,
+            }
+        }
+
+        let vector = |indices: &[Option<usize>; 3]| {
+            let value = |index: Option<usize>| index.map_or(0.0, |index| values[index]);
+            glm::dvec3(value(indices[0]), value(indices[1]), value(indices[2]))
+        };
+        positions.push(vector(&position));
+        if has_normals {
+            normals.push(vector(&normal));
+        }
+        if has_colors {
+            // 8 bit colors are sRGB like textures, floating point ones are taken to be linear
+            let channel = |index: Option<usize>| {
+                let index = index.unwrap_or_default();
+                match element.properties[index].1 {
+                    PropertyType::Scalar(ScalarType::U8) => srgb_eotf(values[index] / 255.0),
+                    PropertyType::Scalar(ScalarType::U16) => srgb_eotf(values[index] / 65535.0),
+                    _ => values[index],
+                }
+            };
+            colors.push(glm::dvec3(
+                channel(color[0]),
+                channel(color[1]),
+                channel(color[2]),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    reader: &mut Reader,
+    element: &Element,
+    vertex_count: usize,
+    indices: &mut Vec<[u32; 3]>,
+) -> Result<(), LoadError> {
+    let list = element
+        .properties
+        .iter()
+        .position(|(name, _)| name == "vertex_indices" || name == "vertex_index")
+        .ok_or_else(|| reader.error("faces need a vertex_indices list"))?;
+
+    let mut corners: Vec<u32> = vec![];
+    for _ in 0..element.count {
+        for (index, (_, property)) in element.properties.iter().enumerate() {
+            match (index == list, property) {
+                (true, PropertyType::List(count_type, item_type)) => {
+                    let count = reader.read(*count_type)? as usize;
+                    corners.clear();
+                    for _ in 0..count {
+                        let corner = reader.read(*item_type)?;
+                        if corner < 0.0 || corner as usize >= vertex_count {
+                            return Err(reader.error(format!(
+                                "vertex index {} is out of range, there are {}",
+                                corner, vertex_count
+                            )));
+                        }
+                        corners.push(corner as u32);
+                    }
+                }
+                (true, PropertyType::Scalar(_)) => {
+                    return Err(reader.error("vertex_indices has to be a list"));
+                }
+                (false, property) => reader.skip(*property)?,
+            }
+        }
+
+        // Polygons are split into a fan around the first vertex
+        for i in 1..corners.len().saturating_sub(1) {
+            indices.push([corners[0], corners[i], corners[i + 1]]);
+        }
+    }
+    Ok(())
+}
+
+// Reads the values following the header, errors point at the line or byte they happened at
+struct Reader<'a> {
+    path: &'a str,
+    data: &'a [u8],
+    offset: usize,
+    format: Format,
+
+    // ASCII only
+    lines: std::str::Lines<'a>,
+    tokens: SplitWhitespace<'a>,
+    line_number: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(
+        path: &'a str,
+        data: &'a [u8],
+        format: Format,
+        header_lines: usize,
+        body_start: usize,
+    ) -> Result<Reader<'a>, LoadError> {
+        let body = match format {
+            Format::Ascii => std::str::from_utf8(&data[body_start..])
+                .map_err(|_| LoadError::parse(path, header_lines + 1, "not valid ascii"))?,
+            _ => "",
+        };
+        Ok(Reader {
+            path,
+            data,
+            offset: body_start,
+            format,
+            lines: body.lines(),
+            tokens: "".split_whitespace(),
+            line_number: header_lines,
+        })
+    }
+
+    fn error(&self, message: impl Into<String>) -> LoadError {
+        match self.format {
+            Format::Ascii => LoadError::parse(self.path, self.line_number, message),
+            _ => LoadError::binary(self.path, self.offset, message),
+        }
+    }
+
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64, LoadError> {
+        if self.format == Format::Ascii {
+            let token = loop {
+                if let Some(token) = self.tokens.next() {
+                    break token;
+                }
+                self.line_number += 1;
+                self.tokens = self
+                    .lines
+                    .next()
+                    .ok_or_else(|| self.error("unexpected end of file"))?
+                    .split_whitespace();
+            };
+            return token
+                .parse()
+                .map_err(|_| self.error(format!("expected a number, found '{}'", token)));
+        }
+
+        let size = scalar_type.size();
+        if self.offset + size > self.data.len() {
+            return Err(self.error("unexpected end of file"));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.offset..self.offset + size]);
+        if self.format == Format::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+        self.offset += size;
+
+        // Everything is little endian from here on
+        Ok(match scalar_type {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+
+    fn skip(&mut self, property: PropertyType) -> Result<(), LoadError> {
+        match property {
+            PropertyType::Scalar(scalar_type) => {
+                self.read(scalar_type)?;
+            }
+            PropertyType::List(count_type, item_type) => {
+                let count = self.read(count_type)? as usize;
+                for _ in 0..count {
+                    self.read(item_type)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{error_line, test_file};
+
+    const HEADER: &str = "ply\nformat ascii 1.0\ncomment a single triangle\nelement vertex 3\n\
+                          property float x\nproperty float y\nproperty float z\nelement face 1\n\
+                          property list uchar int vertex_indices\nend_header\n";
+
+    fn load(name: &str, contents: impl AsRef<[u8]>) -> Result<Scene, LoadError> {
+        let mut scene = Scene::empty();
+        load_ply(&test_file(name, contents), &mut scene)?;
+        Ok(scene)
+    }
+
+    #[test]
+    fn loads_ascii_faces() {
+        let scene = load(
+            "triangle.ply",
+            format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n", HEADER),
+        );
+        assert_eq!(scene.unwrap().meshes[0].triangle_count(), 1);
+    }
+
+    #[test]
+    fn header_errors_point_at_the_line() {
+        let header = HEADER.replace("property float y", "property flot y");
+        let err = load(
+            "header.ply",
+            format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n", header),
+        );
+        assert_eq!(error_line(err.err().unwrap()), 6);
+
+        let err = load("end.ply", "ply\nformat ascii 1.0\nelement vertex 3\n")
+            .err()
+            .unwrap();
+        assert_eq!(error_line(err), 4);
+    }
+
+    #[test]
+    fn ascii_errors_point_at_the_line() {
+        let err = load(
+            "number.ply",
+            format!("{}0 0 0\n1 zero 0\n0 1 0\n3 0 1 2\n", HEADER),
+        );
+        assert_eq!(error_line(err.err().unwrap()), 12);
+
+        let err = load(
+            "index.ply",
+            format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1 5\n", HEADER),
+        );
+        assert_eq!(error_line(err.err().unwrap()), 14);
+    }
+
+    #[test]
+    fn binary_errors_point_at_the_byte() {
+        let header = HEADER.replace("ascii", "binary_little_endian");
+        let mut data = header.clone().into_bytes();
+        // Seven and a half of the nine coordinates
+        for coordinate in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0] {
+            data.extend_from_slice(&coordinate.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0]);
+
+        match load("truncated.ply", data).err().unwrap() {
+            LoadError::Binary { offset, .. } => assert_eq!(offset, header.len() + 28),
+            err => panic!("expected a binary error, got '{}'", err),
+        }
+    }
+}
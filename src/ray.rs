@@ -1,11 +1,22 @@
 pub struct Ray {
     origin: glm::DVec3,
     direction: glm::DVec3,
+    // When the ray was sent during the shutter interval, moving objects are hit where they are
+    // at that time
+    time: f64,
 }
 
 impl Ray {
     pub fn new(origin: glm::DVec3, direction: glm::DVec3) -> Ray {
-        Ray { origin, direction }
+        Ray::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: glm::DVec3, direction: glm::DVec3, time: f64) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn origin(&self) -> &glm::DVec3 {
@@ -16,6 +27,10 @@ impl Ray {
         &self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
     pub fn at(&self, t: f64) -> glm::DVec3 {
         self.origin + self.direction * t
     }
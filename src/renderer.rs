@@ -1,36 +1,139 @@
-use std::fs::File;
-use std::io::Write as _;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use rayon::prelude::*;
 
 use crate::{
-    camera::Camera,
+    camera::{Camera, Eye},
+    light::{PunctualLight, PunctualSample},
+    material::Material,
+    output::{self, ImageFormat, SaveError},
     ray::Ray,
-    scene::{Scene, Sphere},
-    utils::{self, near_zero, random_f64, random_vec3_range, some_kind_of_gamma},
-    vec3::Color3,
+    sampler::Sampler,
+    scene::{Intersection, Scene},
+    tonemap::DisplayTransform,
+    utils::{random_f64, random_vec3_range},
+    vec3::{Color3, Vec3},
 };
 
+const TILE_ROWS: usize = 8;
+
+fn build_thread_pool(thread_count: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(thread_count)
+        .build()
+        .unwrap()
+}
+
 fn vec4_to_u32(vec: &glm::DVec4) -> u32 {
     let r = (255.0 * vec.x) as u32;
     let g = (255.0 * vec.y) as u32;
     let b = (255.0 * vec.z) as u32;
     let a = (255.0 * vec.w) as u32;
 
-    return r + (g << 8) + (b << 16) + (a << 24);
+    r + (g << 8) + (b << 16) + (a << 24)
 }
 
 // --------------- Utils ---------------
 
+// How the two eyes of a stereo pair are packed into one image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    // Left eye on the left
+    SideBySide,
+    // Left eye on top
+    TopBottom,
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "side-by-side" | "sbs" => Ok(StereoLayout::SideBySide),
+            "top-bottom" | "tb" => Ok(StereoLayout::TopBottom),
+            _ => Err(format!("unknown stereo layout '{}'", name)),
+        }
+    }
+}
+
+// Which of the two renderers draws the samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    // `render`, reflections lit by a fixed light, a quick look at the scene
+    Preview,
+    // `render_recurse`, the path tracer from the book
+    Path,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "preview" | "render" => Ok(Integrator::Preview),
+            "path" | "recurse" | "render-recurse" => Ok(Integrator::Path),
+            _ => Err(format!("unknown integrator '{}'", name)),
+        }
+    }
+}
+
+// How `render_recurse` finds the lights of the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSampling {
+    // Only by bouncing into them, converges very slowly for small lights
+    Off,
+    // By sampling a light at every diffuse bounce, lights hit by those bounces are left out
+    Direct,
+    // Both, weighted with the balance or the power heuristic of multiple importance sampling.
+    // Large lights are found better by bouncing, small ones by sampling them.
+    Balance,
+    Power,
+}
+
+impl LightSampling {
+    // Weight of a light found with density `pdf`, when the other way finds it with `other_pdf`
+    fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        match self {
+            LightSampling::Off | LightSampling::Direct => 1.0,
+            // Neither way finds it
+            _ if pdf <= 0.0 => 0.0,
+            LightSampling::Balance => pdf / (pdf + other_pdf),
+            LightSampling::Power => pdf * pdf / (pdf * pdf + other_pdf * other_pdf),
+        }
+    }
+}
+
+impl FromStr for LightSampling {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "off" | "none" => Ok(LightSampling::Off),
+            "direct" | "nee" => Ok(LightSampling::Direct),
+            "balance" => Ok(LightSampling::Balance),
+            "power" | "mis" => Ok(LightSampling::Power),
+            _ => Err(format!("unknown light sampling '{}'", name)),
+        }
+    }
+}
+
 // --------------- Renderer ---------------
 
 #[derive(Clone)]
-struct HitPayload {
-    hit_distance: f64,
-    world_position: glm::DVec3,
-    world_normal: glm::DVec3,
-    object_index: i32,
+pub(crate) struct HitPayload {
+    pub(crate) hit_distance: f64,
+    pub(crate) world_position: glm::DVec3,
+    pub(crate) world_normal: glm::DVec3,
+    pub(crate) object_index: i32,
+    pub(crate) material_index: usize,
+    // Texture coordinates, interpolated from the vertices on meshes
+    pub(crate) uv: glm::DVec2,
+    // Interpolated vertex color on meshes that have them, white everywhere else
+    pub(crate) color: Color3,
 
     // ray tracing in one weekend
-    front_face: bool,
+    pub(crate) front_face: bool,
 }
 
 impl Default for HitPayload {
@@ -40,6 +143,9 @@ impl Default for HitPayload {
             world_position: glm::dvec3(0.0, 0.0, 0.0),
             world_normal: glm::dvec3(0.0, 0.0, 0.0),
             object_index: Default::default(),
+            material_index: Default::default(),
+            uv: glm::dvec2(0.0, 0.0),
+            color: glm::dvec3(1.0, 1.0, 1.0),
             front_face: Default::default(),
         }
     }
@@ -51,20 +157,88 @@ pub struct Renderer {
     height: usize,
     accum: Vec<glm::DVec4>,
     frame_index: usize,
+    display_transform: DisplayTransform,
+
+    // Size of one eye's view, the image holds two of them when rendering stereo
+    eye_width: usize,
+    eye_height: usize,
+    stereo_layout: Option<StereoLayout>,
+
+    // Multithreading, the image is split into tiles of `TILE_ROWS` full rows
+    thread_pool: rayon::ThreadPool,
+    thread_count: usize,
+    // All random numbers are derived from this, so renders can be reproduced
+    seed: u64,
+    // Bounces before `render_recurse` gives up on a path
+    max_depth: u32,
+    light_sampling: LightSampling,
 }
 
 impl Renderer {
     pub fn new() -> Renderer {
+        let thread_count = std::thread::available_parallelism().map_or(1, |n| n.get());
         Renderer {
             pixels: vec![],
             width: 0,
             height: 0,
             accum: vec![],
             frame_index: 0,
+            display_transform: DisplayTransform::default(),
+
+            eye_width: 0,
+            eye_height: 0,
+            stereo_layout: None,
+
+            thread_pool: build_thread_pool(thread_count),
+            thread_count,
+            seed: 0,
+            max_depth: 50,
+            light_sampling: LightSampling::Power,
         }
     }
 
-    pub fn on_resize(&mut self, width: usize, height: usize) {
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.thread_count = thread_count.max(1);
+        self.thread_pool = build_thread_pool(self.thread_count);
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_light_sampling(&mut self, light_sampling: LightSampling) {
+        self.light_sampling = light_sampling;
+    }
+
+    // Only changes how the accumulated colors are displayed, so it can be changed between frames
+    pub fn set_display_transform(&mut self, display_transform: DisplayTransform) {
+        self.display_transform = display_transform;
+    }
+
+    // Clears the image, the size passed to `on_resize` stays the size of a single eye
+    pub fn set_stereo_layout(&mut self, stereo_layout: Option<StereoLayout>) {
+        self.stereo_layout = stereo_layout;
+        self.on_resize(self.eye_width, self.eye_height);
+    }
+
+    // The size of what the camera sees, stereo images are twice as wide or high
+    pub fn on_resize(&mut self, eye_width: usize, eye_height: usize) {
+        self.eye_width = eye_width;
+        self.eye_height = eye_height;
+        let (width, height) = match self.stereo_layout {
+            None => (eye_width, eye_height),
+            Some(StereoLayout::SideBySide) => (2 * eye_width, eye_height),
+            Some(StereoLayout::TopBottom) => (eye_width, 2 * eye_height),
+        };
+
         self.pixels = vec![0; width * height];
         self.accum = vec![glm::dvec4(0.0, 0.0, 0.0, 1.0); width * height];
         self.frame_index = 0;
@@ -72,29 +246,138 @@ impl Renderer {
         self.height = height;
     }
 
+    // The size of the whole image, both eyes when rendering stereo
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn render(&mut self, camera: &Camera, scene: &Scene) {
-        self.frame_index += 1;
-        for j in 0..self.height {
-            for i in 0..self.width {
-                let color = self.per_pixel(i, j, camera, scene);
-                self.accum[i + j * self.width] = self.accum[i + j * self.width] + color;
-
-                let mut accumulated_color = self.accum[i + j * self.width];
-                accumulated_color = accumulated_color / self.frame_index as f64;
-                accumulated_color = glm::clamp(
-                    accumulated_color,
-                    glm::dvec4(0.0, 0.0, 0.0, 0.0),
-                    glm::dvec4(1.0, 1.0, 1.0, 1.0),
-                );
-                self.pixels[i + j * self.width] = vec4_to_u32(&accumulated_color);
+        self.render_tiles(|renderer, eye, i, j, sampler| {
+            renderer.per_pixel(eye, i, j, camera, scene, sampler)
+        });
+    }
+
+    // One more sample for every pixel
+    pub fn render_with(&mut self, integrator: Integrator, camera: &Camera, scene: &Scene) {
+        match integrator {
+            Integrator::Preview => self.render(camera, scene),
+            Integrator::Path => self.render_recurse(camera, scene),
+        }
+    }
+
+    // Which eye a pixel of the image belongs to and where it is in that eye's view
+    fn eye_pixel(&self, i: usize, j: usize) -> (Option<Eye>, usize, usize) {
+        match self.stereo_layout {
+            None => (None, i, j),
+            Some(StereoLayout::SideBySide) if i < self.eye_width => (Some(Eye::Left), i, j),
+            Some(StereoLayout::SideBySide) => (Some(Eye::Right), i - self.eye_width, j),
+            // Rows are stored bottom up, so the left eye is the upper half
+            Some(StereoLayout::TopBottom) if j >= self.eye_height => {
+                (Some(Eye::Left), i, j - self.eye_height)
             }
+            Some(StereoLayout::TopBottom) => (Some(Eye::Right), i, j),
         }
     }
 
-    fn per_pixel(&mut self, x: usize, y: usize, camera: &Camera, scene: &Scene) -> glm::DVec4 {
-        let u = (x as f64 + random_f64()) / self.width as f64;
-        let v = (y as f64 + random_f64()) / self.height as f64;
-        let mut ray = camera.get_ray(u, v);
+    fn camera_ray(
+        camera: &Camera,
+        eye: Option<Eye>,
+        u: f64,
+        v: f64,
+        sampler: &mut Sampler,
+    ) -> Option<Ray> {
+        match eye {
+            Some(eye) => camera.get_eye_ray(u, v, eye, sampler),
+            None => camera.get_ray(u, v, sampler),
+        }
+    }
+
+    // Renders one sample for every pixel, tiles are spread over the thread pool. The pixel
+    // passed to sample_pixel is relative to the eye it belongs to.
+    fn render_tiles<F>(&mut self, sample_pixel: F)
+    where
+        F: Fn(&Renderer, Option<Eye>, usize, usize, &mut Sampler) -> glm::DVec4 + Sync,
+    {
+        self.frame_index += 1;
+
+        // Take the buffers out, so each tile can borrow its own slice while sharing the renderer
+        let mut accum = std::mem::take(&mut self.accum);
+        let mut pixels = std::mem::take(&mut self.pixels);
+
+        let renderer = &*self;
+        let tile_len = (TILE_ROWS * self.width).max(1);
+        let render_tile =
+            |(tile_index, (accum_tile, pixel_tile)): (usize, (&mut [glm::DVec4], &mut [u32]))| {
+                let first_pixel = tile_index * tile_len;
+                for (offset, (accum_pixel, pixel)) in
+                    accum_tile.iter_mut().zip(pixel_tile.iter_mut()).enumerate()
+                {
+                    let i = (first_pixel + offset) % renderer.width;
+                    let j = (first_pixel + offset) / renderer.width;
+                    let mut sampler = Sampler::new(renderer.seed, i, j, renderer.frame_index);
+
+                    // Accumulating color
+                    let (eye, x, y) = renderer.eye_pixel(i, j);
+                    let color = sample_pixel(renderer, eye, x, y, &mut sampler);
+                    *accum_pixel = *accum_pixel + color;
+
+                    // Setting pixel color
+                    *pixel = vec4_to_u32(&renderer.display_color(accum_pixel));
+                }
+            };
+
+        if self.thread_count == 1 {
+            accum
+                .chunks_mut(tile_len)
+                .zip(pixels.chunks_mut(tile_len))
+                .enumerate()
+                .for_each(render_tile);
+        } else {
+            self.thread_pool.install(|| {
+                accum
+                    .par_chunks_mut(tile_len)
+                    .zip(pixels.par_chunks_mut(tile_len))
+                    .enumerate()
+                    .for_each(render_tile);
+            });
+        }
+
+        self.accum = accum;
+        self.pixels = pixels;
+    }
+
+    fn per_pixel(
+        &self,
+        eye: Option<Eye>,
+        x: usize,
+        y: usize,
+        camera: &Camera,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> glm::DVec4 {
+        let u = (x as f64 + random_f64(sampler)) / self.eye_width as f64;
+        let v = (y as f64 + random_f64(sampler)) / self.eye_height as f64;
+        let mut ray = match Self::camera_ray(camera, eye, u, v, sampler) {
+            Some(ray) => ray,
+            None => return glm::dvec4(0.0, 0.0, 0.0, 1.0),
+        };
+
+        // Scenes without punctual lights get a fixed one, so there is something to see. It lights
+        // a white surface facing it to 1.
+        let preview_light = [PunctualLight::Directional {
+            direction: glm::dvec3(-1.0, -1.0, -1.0),
+            irradiance: glm::dvec3(PI, PI, PI),
+            angular_diameter: 0.0,
+        }];
+        let lights = if scene.punctual_lights.is_empty() {
+            &preview_light[..]
+        } else {
+            &scene.punctual_lights[..]
+        };
 
         let mut color = glm::dvec3(0.0, 0.0, 0.0);
         let mut multiplier = 1.0;
@@ -104,91 +387,52 @@ impl Renderer {
             let payload: HitPayload = self.trace_ray(&ray, camera, scene);
 
             if payload.hit_distance < 0.0 {
-                let unit_direction = glm::normalize(*ray.direction());
-                let t = 0.5 * (unit_direction.y + 1.0);
-                let sky_color =
-                    glm::dvec3(1.0, 1.0, 1.0) * (1.0 - t) + glm::dvec3(0.5, 0.7, 1.0) * t;
-                color = color + sky_color * multiplier;
+                color = color + scene.background.color(ray.direction()) * multiplier;
                 break;
             }
 
-            let light_dir = glm::normalize(glm::dvec3(-1.0, -1.0, -1.0));
-            let light_intensity = glm::max(glm::dot(payload.world_normal, -light_dir), 0.0); // cos(angle)
-
-            let sphere = &scene.spheres[payload.object_index as usize];
-            let material = &scene.materials[sphere.material_index()];
+            let material = &scene.materials[payload.material_index];
+            let new_origin = payload.world_position + payload.world_normal * 0.0001;
 
-            let mut sphere_color = material.albedo;
-            sphere_color = sphere_color * light_intensity;
-            color = color + sphere_color * multiplier;
+            // Everything is shaded as if it were diffuse
+            let mut irradiance = glm::dvec3(0.0, 0.0, 0.0);
+            for light in lights {
+                let sample = match light.sample(&new_origin, sampler) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let cosine = glm::dot(payload.world_normal, sample.direction);
+                if cosine > 0.0 && !Self::shadowed(scene, &new_origin, &sample, ray.time()) {
+                    irradiance = irradiance + sample.irradiance * cosine;
+                }
+            }
+            let sphere_color = material.albedo(&payload) * irradiance / PI;
+            color = color + (sphere_color + material.emitted()) * multiplier;
 
             multiplier *= 0.5;
 
-            let new_origin = payload.world_position + payload.world_normal * 0.0001;
             let new_direction = glm::reflect(
                 *ray.direction(),
-                payload.world_normal + random_vec3_range(-0.5, 0.5) * material.roughness,
+                payload.world_normal + random_vec3_range(sampler, -0.5, 0.5) * material.roughness(),
             );
-            ray = Ray::new(new_origin, new_direction);
+            ray = Ray::with_time(new_origin, new_direction, ray.time());
         }
 
-        return glm::dvec4(color.x, color.y, color.z, 1.0);
+        glm::dvec4(color.x, color.y, color.z, 1.0)
     }
 
-    fn trace_ray(&mut self, ray: &Ray, _camera: &Camera, scene: &Scene) -> HitPayload {
-        let mut closest_sphere = -1;
-        let mut hit_distance = f64::INFINITY;
-
-        for (i, sphere) in scene.spheres.iter().enumerate() {
-            // Sphere intersection
-            let origin = *ray.origin() - *sphere.center();
-            let a = glm::dot(*ray.direction(), *ray.direction());
-            let b = 2.0 * glm::dot(origin, *ray.direction());
-            let c = glm::dot(origin, origin) - sphere.radius() * sphere.radius();
-            let discriminant = b * b - 4.0 * a * c;
-
-            if discriminant < 0.0 {
-                continue;
-            }
-
-            let closest_t = (-b - discriminant.sqrt()) / (2.0 * a);
-            if closest_t > 0.0 && closest_t < hit_distance {
-                closest_sphere = i as i32;
-                hit_distance = closest_t;
-            }
-        }
-
-        if closest_sphere < 0 {
-            self.miss(ray)
-        } else {
-            self.closest_hit(ray, hit_distance, closest_sphere, scene)
+    fn trace_ray(&self, ray: &Ray, _camera: &Camera, scene: &Scene) -> HitPayload {
+        match scene.intersect(ray, 0.0, f64::INFINITY) {
+            Some(hit) => self.closest_hit(ray, &hit, scene),
+            None => self.miss(ray),
         }
     }
 
-    fn closest_hit(
-        &mut self,
-        ray: &Ray,
-        hit_distance: f64,
-        object_index: i32,
-        scene: &Scene,
-    ) -> HitPayload {
-        let sphere = &scene.spheres[object_index as usize];
-
-        let origin = *ray.origin() - *sphere.center();
-        let mut world_position = origin + *ray.direction() * hit_distance;
-        let world_normal = glm::normalize(world_position);
-        world_position = world_position + *sphere.center();
-
-        HitPayload {
-            hit_distance,
-            world_position,
-            world_normal,
-            object_index,
-            ..Default::default()
-        }
+    fn closest_hit(&self, ray: &Ray, hit: &Intersection, scene: &Scene) -> HitPayload {
+        scene.hit_payload(ray, hit)
     }
 
-    fn miss(&mut self, _ray: &Ray) -> HitPayload {
+    fn miss(&self, _ray: &Ray) -> HitPayload {
         HitPayload {
             hit_distance: -1.0,
             ..Default::default()
@@ -201,40 +445,33 @@ impl Renderer {
     // Instead of doing anti-aliasing by sampling the pixel, we just accumulate the color
 
     pub fn render_recurse(&mut self, camera: &Camera, scene: &Scene) {
-        let max_depth = 50;
+        let max_depth = self.max_depth;
 
-        self.frame_index += 1;
+        self.render_tiles(|renderer, eye, i, j, sampler| {
+            // Calculating u, v
+            let u = (i as f64 + random_f64(sampler)) / (renderer.eye_width - 1) as f64;
+            let v = (j as f64 + random_f64(sampler)) / (renderer.eye_height - 1) as f64;
 
-        for j in 0..self.height {
-            for i in 0..self.width {
-                // Calculating u, v
-                let u = (i as f64 + random_f64()) / (self.width - 1) as f64;
-                let v = (j as f64 + random_f64()) / (self.height - 1) as f64;
-
-                // Calculating ray
-                let ray = camera.get_ray(u, v);
-                let color = self.pixel_color(&ray, scene, max_depth);
-
-                // Accumulating color
-                self.accum[i + j * self.width] =
-                    self.accum[i + j * self.width] + glm::dvec4(color.x, color.y, color.z, 1.0);
-
-                // Averaging color
-                let mut accum_color = self.accum[i + j * self.width] / self.frame_index as f64;
-                accum_color = glm::clamp(
-                    accum_color,
-                    glm::dvec4(0.0, 0.0, 0.0, 0.0),
-                    glm::dvec4(1.0, 1.0, 1.0, 1.0),
-                );
-                accum_color = some_kind_of_gamma(&accum_color);
-
-                // Setting pixel color
-                self.pixels[i + j * self.width] = vec4_to_u32(&accum_color);
-            }
-        }
+            // Calculating ray
+            let color = match Self::camera_ray(camera, eye, u, v, sampler) {
+                Some(ray) => renderer.pixel_color(&ray, scene, max_depth, None, sampler),
+                None => glm::dvec3(0.0, 0.0, 0.0),
+            };
+            glm::dvec4(color.x, color.y, color.z, 1.0)
+        });
     }
 
-    fn pixel_color(&mut self, ray: &Ray, scene: &Scene, depth: u32) -> Color3 {
+    // Lights hit by rays that left a diffuse bounce were also sampled at that bounce, the
+    // density that bounce was picked with weighs the two against each other. None for camera
+    // rays and specular bounces, whose lights are counted in full.
+    fn pixel_color(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        depth: u32,
+        bounce_pdf: Option<f64>,
+        sampler: &mut Sampler,
+    ) -> Color3 {
         // If we've exceeded the ray bounce limit, no more light is gathered.
         if depth == 0 {
             return glm::dvec3(0.0, 0.0, 0.0);
@@ -242,177 +479,266 @@ impl Renderer {
 
         let mut rec = HitPayload::default();
         if self.world_hit(scene, ray, 0.001, f64::MAX, &mut rec) {
-            let mut scattered = Ray::default();
-            let mut attenuation = glm::dvec3(0.0, 0.0, 0.0);
-
-            let sphere = &scene.spheres[rec.object_index as usize];
-            if self.scatter(
-                sphere.material_index(),
-                scene,
-                ray,
-                &mut rec,
-                &mut attenuation,
-                &mut scattered,
-            ) {
-                return attenuation * self.pixel_color(&scattered, scene, depth - 1);
+            let material = &scene.materials[rec.material_index];
+
+            let emitted = material.emitted();
+            let mut color = match (bounce_pdf, self.light_sampling) {
+                (None, _) => emitted,
+                (Some(_), LightSampling::Direct) => glm::dvec3(0.0, 0.0, 0.0),
+                (Some(pdf), light_sampling) => {
+                    let light_pdf =
+                        scene.light_pdf(ray, rec.object_index as usize, rec.hit_distance);
+                    emitted * light_sampling.weight(pdf, light_pdf)
+                }
+            };
+            if self.light_sampling != LightSampling::Off {
+                color = color + self.direct_light(ray, &rec, material, scene, sampler);
             }
-
-            return glm::dvec3(0.0, 0.0, 0.0);
+            color = color + self.punctual_light(ray, &rec, material, scene, sampler);
+            return match material.scatter(ray, &rec, sampler) {
+                Some(scattered) => {
+                    let bounce_pdf =
+                        if self.light_sampling == LightSampling::Off || scattered.specular {
+                            None
+                        } else {
+                            Some(material.pdf(ray, &rec, scattered.ray.direction()))
+                        };
+                    color
+                        + scattered.attenuation
+                            * self.pixel_color(
+                                &scattered.ray,
+                                scene,
+                                depth - 1,
+                                bounce_pdf,
+                                sampler,
+                            )
+                }
+                None => color,
+            };
         }
 
-        // Background gradient
-        let unit_direction = glm::normalize(*ray.direction());
-        let t = 0.5 * (unit_direction.y + 1.0);
-        return glm::dvec3(1.0, 1.0, 1.0) * (1.0 - t) + glm::dvec3(0.5, 0.7, 1.0) * t;
+        scene.background.color(ray.direction())
     }
 
-    fn world_hit(
-        &mut self,
+    // Light reflected by the non-specular part of the material straight from a sampled light
+    fn direct_light(
+        &self,
+        ray: &Ray,
+        rec: &HitPayload,
+        material: &Material,
         scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> Color3 {
+        let black = glm::dvec3(0.0, 0.0, 0.0);
+        let light = match scene.sample_light(&rec.world_position, ray.time(), sampler) {
+            Some(light) => light,
+            None => return black,
+        };
+        let reflected = material.evaluate(ray, rec, &light.direction);
+        if reflected == black {
+            return black;
+        }
+
+        // Anything in between casts a shadow, stop short of the light so it doesn't shadow itself
+        let shadow_ray = Ray::with_time(rec.world_position, light.direction, ray.time());
+        if scene
+            .intersect(&shadow_ray, 0.001, light.distance - 0.001)
+            .is_some()
+        {
+            return black;
+        }
+
+        let bounce_pdf = material.pdf(ray, rec, &light.direction);
+        reflected * light.emitted * (self.light_sampling.weight(light.pdf, bounce_pdf) / light.pdf)
+    }
+
+    // Light reflected by the non-specular part of the material from every punctual light. They
+    // can't be hit by bounces, so they are sampled whatever the light sampling is.
+    fn punctual_light(
+        &self,
         ray: &Ray,
-        t_min: f64,
-        t_max: f64,
-        rec: &mut HitPayload,
-    ) -> bool {
-        let mut hit_anything = false;
-        let mut closest_so_far = t_max;
-
-        for (i, sphere) in scene.spheres.iter().enumerate() {
-            let mut temp_rec = HitPayload::default();
-            if self.sphere_hit(sphere, scene, ray, t_min, closest_so_far, &mut temp_rec) {
-                hit_anything = true;
-                closest_so_far = temp_rec.hit_distance;
-                *rec = temp_rec.clone();
-                rec.object_index = i as i32;
+        rec: &HitPayload,
+        material: &Material,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> Color3 {
+        let black = glm::dvec3(0.0, 0.0, 0.0);
+        let mut color = black;
+        for light in &scene.punctual_lights {
+            let sample = match light.sample(&rec.world_position, sampler) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let reflected = material.evaluate(ray, rec, &sample.direction);
+            if reflected != black
+                && !Self::shadowed(scene, &rec.world_position, &sample, ray.time())
+            {
+                color = color + reflected * sample.irradiance;
             }
         }
+        color
+    }
 
-        return hit_anything;
+    fn shadowed(scene: &Scene, position: &Vec3, sample: &PunctualSample, time: f64) -> bool {
+        let shadow_ray = Ray::with_time(*position, sample.direction, time);
+        scene
+            .intersect(&shadow_ray, 0.001, sample.distance - 0.001)
+            .is_some()
     }
 
-    fn sphere_hit(
-        &mut self,
-        sphere: &Sphere,
-        _scene: &Scene,
-        r: &Ray,
+    fn world_hit(
+        &self,
+        scene: &Scene,
+        ray: &Ray,
         t_min: f64,
         t_max: f64,
         rec: &mut HitPayload,
     ) -> bool {
-        let oc = *r.origin() - *sphere.center();
-        let a = glm::length(*r.direction()).powf(2.0);
-        let half_b = glm::dot(oc, *r.direction());
-        let c = glm::length(oc).powf(2.0) - sphere.radius() * sphere.radius();
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return false;
-        }
-
-        let sqrtd = discriminant.sqrt();
-
-        // Find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || t_max < root {
-                return false;
-            }
-        }
-
-        rec.hit_distance = root;
-        rec.world_position = r.at(rec.hit_distance);
-        let outward_normal = (rec.world_position - *sphere.center()) / sphere.radius();
-        let front_face = glm::dot(*r.direction(), outward_normal) < 0.0;
-        rec.world_normal = if front_face {
-            outward_normal
-        } else {
-            -outward_normal
+        let hit = match scene.intersect(ray, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
         };
+        *rec = scene.hit_payload(ray, &hit);
 
         // Offset the hit point to avoid shadow acne
         rec.world_position = rec.world_position + rec.world_normal * 0.0001;
-        rec.front_face = front_face;
 
-        return true;
+        true
     }
 
-    fn scatter(
-        &mut self,
-        material_index: usize,
-        scene: &Scene,
-        r_in: &Ray,
-        rec: &mut HitPayload,
-        attenuation: &mut Color3,
-        scattered: &mut Ray,
-    ) -> bool {
-        let material = &scene.materials[material_index];
-
-        // Dielectrics
-        if material.glass {
-            *attenuation = glm::dvec3(1.0, 1.0, 1.0);
-            let refraction_ratio = if rec.front_face {
-                1.0 / material.refraction_index
-            } else {
-                material.refraction_index
-            };
-
-            // Why do I not get hollow sphere as in the book?
+    // ---------------------------- recursive render ----------------------------
 
-            let unit_direction = glm::normalize(*r_in.direction());
-            let cos_theta = glm::min(glm::dot(-unit_direction, rec.world_normal), 1.0);
-            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    pub fn save_as(&self, filename: &str, format: ImageFormat) -> Result<(), SaveError> {
+        match format {
+            ImageFormat::Ppm => output::write_ppm(filename, self.width, self.height, &self.rgb8()),
+            ImageFormat::Png => output::write_png(filename, self.width, self.height, &self.rgb8()),
+            ImageFormat::Png16 => {
+                output::write_png16(filename, self.width, self.height, &self.rgb16())
+            }
+            ImageFormat::Exr | ImageFormat::Exr32 => output::write_exr(
+                filename,
+                self.width,
+                self.height,
+                &self.radiance(),
+                format == ImageFormat::Exr,
+            ),
+            ImageFormat::Pfm => {
+                output::write_pfm(filename, self.width, self.height, &self.radiance())
+            }
+            ImageFormat::Hdr => {
+                output::write_hdr(filename, self.width, self.height, &self.radiance())
+            }
+        }?;
 
-            let cannot_refract = refraction_ratio * sin_theta > 1.0;
-            let direction = if cannot_refract
-                || (utils::reflectance(cos_theta, refraction_ratio) > utils::random_f64())
-            {
-                glm::reflect(unit_direction, rec.world_normal)
-            } else {
-                glm::refract(unit_direction, rec.world_normal, refraction_ratio)
-            };
+        println!("Saved image to {}", filename);
+        Ok(())
+    }
 
-            *scattered = Ray::new(rec.world_position, direction);
-            return true;
+    // Pixels are stored bottom row first, images are written top row first
+    fn rgb8(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for j in 0..self.height {
+            let y = self.height - 1 - j;
+            for x in 0..self.width {
+                let pixel = self.pixels[x + y * self.width];
+                rgb.push((pixel & 0xFF) as u8);
+                rgb.push(((pixel >> 8) & 0xFF) as u8);
+                rgb.push(((pixel >> 16) & 0xFF) as u8);
+            }
         }
+        rgb
+    }
 
-        // Lamberian and metal
-        let mut scatter_direction = glm::reflect(
-            glm::normalize(*r_in.direction()),
-            rec.world_normal + random_vec3_range(-0.5, 0.5) * material.roughness,
-        );
+    // Same as `rgb8`, but recomputed from the accumulated colors for the extra precision
+    fn rgb16(&self) -> Vec<u16> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for j in 0..self.height {
+            let y = self.height - 1 - j;
+            for x in 0..self.width {
+                let color = self.display_color(&self.accum[x + y * self.width]);
+                rgb.push((65535.0 * color.x) as u16);
+                rgb.push((65535.0 * color.y) as u16);
+                rgb.push((65535.0 * color.z) as u16);
+            }
+        }
+        rgb
+    }
 
-        // Catch degenerate scatter direction
-        if near_zero(&scatter_direction) {
-            scatter_direction = rec.world_normal;
+    // The averaged linear color, without clamping or gamma, for the high dynamic range formats
+    fn radiance(&self) -> Vec<f32> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for j in 0..self.height {
+            let y = self.height - 1 - j;
+            for x in 0..self.width {
+                let color = self.accum[x + y * self.width] / self.frame_index.max(1) as f64;
+                rgb.push(color.x as f32);
+                rgb.push(color.y as f32);
+                rgb.push(color.z as f32);
+            }
         }
+        rgb
+    }
 
-        *scattered = Ray::new(rec.world_position, scatter_direction);
-        *attenuation = material.albedo;
+    // Averages the accumulated color and maps it into the displayable [0, 1] range
+    fn display_color(&self, accum: &glm::DVec4) -> glm::DVec4 {
+        let average = *accum / self.frame_index.max(1) as f64;
+        let color = self
+            .display_transform
+            .apply(glm::dvec3(average.x, average.y, average.z));
+        glm::dvec4(color.x, color.y, color.z, average.w.clamp(0.0, 1.0))
+    }
+}
 
-        return glm::dot(*scattered.direction(), rec.world_normal) > 0.0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene;
+
+    fn render_cornell_box(thread_count: usize, seed: u64) -> Vec<f32> {
+        let mut scene = scene::cornell_box();
+        scene.build_bvh();
+        let mut camera = Camera::new();
+        camera.setup(
+            &glm::dvec3(278.0, 278.0, -800.0),
+            &glm::dvec3(278.0, 278.0, 0.0),
+            &glm::dvec3(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            800.0,
+        );
+        camera.on_resize(24, 40);
+
+        let mut renderer = Renderer::new();
+        renderer.set_thread_count(thread_count);
+        renderer.set_seed(seed);
+        renderer.set_max_depth(8);
+        renderer.on_resize(24, 40);
+        for _ in 0..2 {
+            renderer.render_recurse(&camera, &scene);
+        }
+        renderer.radiance()
     }
 
-    // ---------------------------- recursive render ----------------------------
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let single = render_cornell_box(1, 3);
+        assert_eq!(single, render_cornell_box(3, 3));
+        assert_eq!(single, render_cornell_box(8, 3));
+    }
 
-    pub fn save(&self, filename: &str) {
-        let mut file = File::create(filename).unwrap();
-        let header = format!("P3\n{} {}\n255\n", self.width, self.height);
-        file.write_all(header.as_bytes()).unwrap();
+    #[test]
+    fn seed_changes_the_image() {
+        assert_ne!(render_cornell_box(2, 3), render_cornell_box(2, 4));
+    }
 
-        for j in 0..self.height {
-            let y = self.height - 1 - j;
-            for i in 0..self.width {
-                let x = i;
-                let pixel = self.pixels[x + y * self.width];
-                let r = (pixel >> 0) & 0xFF;
-                let g = (pixel >> 8) & 0xFF;
-                let b = (pixel >> 16) & 0xFF;
-                let line = format!("{} {} {}\n", r, g, b);
-                file.write_all(line.as_bytes()).unwrap();
+    #[test]
+    fn mis_weights_sum_to_one() {
+        for light_sampling in [LightSampling::Balance, LightSampling::Power] {
+            for (light_pdf, bounce_pdf) in [(1.0, 1.0), (0.1, 10.0), (3.0, 0.5), (2.0, 0.0)] {
+                let sum = light_sampling.weight(light_pdf, bounce_pdf)
+                    + light_sampling.weight(bounce_pdf, light_pdf);
+                assert!((sum - 1.0).abs() < 1e-12, "{:?}: {}", light_sampling, sum);
             }
         }
-
-        println!("Saved image to {}", filename);
     }
 }
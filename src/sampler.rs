@@ -0,0 +1,31 @@
+use crate::utils::hash_u64;
+
+// Stateless random numbers: every value is a hash of the global seed, the pixel, the sample
+// index and the dimension (how many numbers were drawn before it). The same seed therefore
+// always gives the same image, no matter which thread renders which pixel.
+pub struct Sampler {
+    seed: u64,
+    dimension: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64, x: usize, y: usize, sample_index: usize) -> Sampler {
+        Sampler {
+            seed: hash_u64(&[seed, x as u64, y as u64, sample_index as u64]),
+            dimension: 0,
+        }
+    }
+
+    // For random numbers that are not tied to a pixel, like generating a scene
+    pub fn from_seed(seed: u64) -> Sampler {
+        Sampler { seed, dimension: 0 }
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        let hash = hash_u64(&[self.seed, self.dimension]);
+        self.dimension += 1;
+        // The top 53 bits fill the mantissa
+        (hash >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -1,35 +1,27 @@
+use std::f64::consts::PI;
+
 use crate::{
-    utils::{random_color, random_f64, random_f64_range},
-    vec3::Vec3,
+    aabb::{bounding_box_moving_sphere, bounding_box_sphere, Aabb},
+    bvh::{Bvh, BvhOptions},
+    light::PunctualLight,
+    material::Material,
+    mesh::Mesh,
+    ray::Ray,
+    renderer::HitPayload,
+    sampler::Sampler,
+    texture::Texture,
+    utils::{random_color, random_f64, random_f64_range, random_in_cone, random_unit_vec3},
+    vec3::{Color3, Vec3},
 };
 
-pub struct Material {
-    pub albedo: Vec3,
-    pub roughness: f64,
-    pub metallic: f64,
-
-    // ray tracing in one weekend
-    pub glass: bool,
-    pub refraction_index: f64,
-}
-
-impl Default for Material {
-    fn default() -> Self {
-        Material {
-            albedo: Vec3::new(1.0, 1.0, 1.0),
-            roughness: 1.0,
-            metallic: 0.0,
-            glass: false,
-            refraction_index: 1.0,
-        }
-    }
-}
-
 pub struct Sphere {
     center: glm::DVec3,
     radius: f64,
     material_index: usize,
-    
+
+    // (time, center) keyframes sorted by time, empty for spheres that don't move. The center
+    // moves in a straight line between them and stays at the first and last one outside.
+    keyframes: Vec<(f64, glm::DVec3)>,
 }
 
 impl Sphere {
@@ -38,6 +30,21 @@ impl Sphere {
             center,
             radius,
             material_index,
+            keyframes: vec![],
+        }
+    }
+
+    pub fn moving(
+        mut keyframes: Vec<(f64, glm::DVec3)>,
+        radius: f64,
+        material_index: usize,
+    ) -> Sphere {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Sphere {
+            center: keyframes.first().map_or(glm::dvec3(0.0, 0.0, 0.0), |k| k.1),
+            radius,
+            material_index,
+            keyframes,
         }
     }
 
@@ -45,49 +52,225 @@ impl Sphere {
         self.radius
     }
 
-    pub fn center(&self) -> &glm::DVec3 {
-        &self.center
+    pub fn center(&self, time: f64) -> glm::DVec3 {
+        let next = self.keyframes.partition_point(|k| k.0 <= time);
+        if next == 0 || next == self.keyframes.len() {
+            return self
+                .keyframes
+                .get(next.saturating_sub(1))
+                .map_or(self.center, |k| k.1);
+        }
+
+        let (time0, center0) = self.keyframes[next - 1];
+        let (time1, center1) = self.keyframes[next];
+        center0 + (center1 - center0) * ((time - time0) / (time1 - time0))
     }
 
     pub fn material_index(&self) -> usize {
         self.material_index
     }
+
+    pub fn keyframes(&self) -> &[(f64, glm::DVec3)] {
+        &self.keyframes
+    }
+
+    // Covers the whole motion, so the BVH works for rays at any time
+    pub fn bounding_box(&self) -> Aabb {
+        if self.keyframes.is_empty() {
+            return bounding_box_sphere(self.center, self.radius);
+        }
+        let centers: Vec<glm::DVec3> = self.keyframes.iter().map(|k| k.1).collect();
+        bounding_box_moving_sphere(&centers, self.radius)
+    }
+
+    // Returns the distance to the nearest intersection within [t_min, t_max]
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let oc = *ray.origin() - self.center(ray.time());
+        let a = glm::dot(*ray.direction(), *ray.direction());
+        let half_b = glm::dot(oc, *ray.direction());
+        let c = glm::dot(oc, oc) - self.radius * self.radius;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrtd = discriminant.sqrt();
+
+        // Find the nearest root that lies in the acceptable range.
+        let mut root = (-half_b - sqrtd) / a;
+        if root < t_min || t_max < root {
+            root = (-half_b + sqrtd) / a;
+            if root < t_min || t_max < root {
+                return None;
+            }
+        }
+
+        Some(root)
+    }
+
+    // A direction from `position` towards the sphere, spread evenly over the cone the sphere
+    // covers. From inside the sphere points are spread evenly over its surface instead.
+    // Returns the direction, the distance to the surface and the solid angle density.
+    pub fn sample_direction(
+        &self,
+        position: &Vec3,
+        time: f64,
+        sampler: &mut Sampler,
+    ) -> Option<(Vec3, f64, f64)> {
+        let center = self.center(time);
+        let to_center = center - *position;
+        let distance_squared = glm::dot(to_center, to_center);
+        let radius_squared = self.radius * self.radius;
+
+        if distance_squared <= radius_squared {
+            let normal = random_unit_vec3(sampler);
+            let to_point = center + normal * self.radius - *position;
+            let distance = glm::length(to_point);
+            let pdf = self.direction_pdf(position, &(center + normal * self.radius), time);
+            if distance == 0.0 || pdf == 0.0 {
+                return None;
+            }
+            return Some((to_point / distance, distance, pdf));
+        }
+
+        let one_minus_cos = one_minus_cos_theta_max(radius_squared, distance_squared);
+        let axis = to_center / distance_squared.sqrt();
+        let direction = random_in_cone(sampler, &axis, one_minus_cos);
+
+        let ray = Ray::with_time(*position, direction, time);
+        let distance = self.hit(&ray, 0.0, f64::INFINITY)?;
+        Some((direction, distance, 1.0 / (2.0 * PI * one_minus_cos)))
+    }
+
+    // Solid angle density `sample_direction` picks the direction from `position` to `point` on
+    // the sphere with
+    pub fn direction_pdf(&self, position: &Vec3, point: &Vec3, time: f64) -> f64 {
+        let center = self.center(time);
+        let to_center = center - *position;
+        let distance_squared = glm::dot(to_center, to_center);
+        let radius_squared = self.radius * self.radius;
+
+        if distance_squared <= radius_squared {
+            let to_point = *point - *position;
+            let normal = (*point - center) / self.radius;
+            let cosine = glm::dot(normal, glm::normalize(to_point)).abs();
+            if cosine < 1e-8 {
+                return 0.0;
+            }
+            let area = 4.0 * PI * radius_squared;
+            return glm::dot(to_point, to_point) / (cosine * area);
+        }
+        1.0 / (2.0 * PI * one_minus_cos_theta_max(radius_squared, distance_squared))
+    }
+}
+
+// 1 - cos of the half angle of the cone a sphere covers, written so it keeps its precision for
+// small and far spheres
+fn one_minus_cos_theta_max(radius_squared: f64, distance_squared: f64) -> f64 {
+    let sin_squared = radius_squared / distance_squared;
+    sin_squared / (1.0 + (1.0 - sin_squared).sqrt())
+}
+
+// What the BVH is built over, triangles are referenced by mesh and triangle index
+#[derive(Debug, Clone, Copy)]
+enum Primitive {
+    Sphere(usize),
+    Triangle(usize, usize),
+}
+
+// Closest hit found by `Scene::intersect`
+#[derive(Debug, Clone, Copy)]
+pub struct Intersection {
+    pub(crate) primitive: usize,
+    pub(crate) t: f64,
+    // Barycentric coordinates of the second and third vertex for triangles
+    pub(crate) barycentric: (f64, f64),
+}
+
+// A direction towards a point on a light, picked by `Scene::sample_light`
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    pub emitted: Color3,
+    // Solid angle density of the direction, picking the light included
+    pub pdf: f64,
+}
+
+// What rays see when they hit nothing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    // White at the horizon to blue overhead, the only light of the scenes in the first book
+    Sky,
+    // Black for interiors that are lit by their emissive materials alone
+    Solid(Color3),
+}
+
+impl Background {
+    pub fn color(&self, direction: &Vec3) -> Color3 {
+        match self {
+            Background::Sky => {
+                let unit_direction = glm::normalize(*direction);
+                let t = 0.5 * (unit_direction.y + 1.0);
+                glm::dvec3(1.0, 1.0, 1.0) * (1.0 - t) + glm::dvec3(0.5, 0.7, 1.0) * t
+            }
+            Background::Solid(color) => *color,
+        }
+    }
 }
 
 pub struct Scene {
     pub(crate) spheres: Vec<Sphere>,
+    pub(crate) meshes: Vec<Mesh>,
     pub(crate) materials: Vec<Material>,
+    pub(crate) background: Background,
+    pub(crate) bvh: Bvh,
+    primitives: Vec<Primitive>,
+    // Primitives with an emissive material, indices into `primitives`
+    lights: Vec<usize>,
+    pub(crate) punctual_lights: Vec<PunctualLight>,
 }
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene {
+        let mut scene = Scene {
             spheres: vec![
-                Sphere {
-                    center: glm::dvec3(0.0, 0.0, -2.0),
-                    radius: 1.0,
-                    material_index: 0,
-                },
-                Sphere {
-                    center: glm::dvec3(0.0, -101.0, -2.0),
-                    radius: 100.0,
-                    material_index: 1,
-                },
+                Sphere::new(glm::dvec3(0.0, 0.0, -2.0), 1.0, 0),
+                Sphere::new(glm::dvec3(0.0, -101.0, -2.0), 100.0, 1),
             ],
             materials: vec![
-                Material {
+                Material::Metal {
                     albedo: glm::dvec3(1.0, 0.0, 1.0),
-                    roughness: 0.0,
-                    metallic: 0.0,
-                    ..Default::default()
+                    fuzz: 0.0,
                 },
-                Material {
+                Material::Metal {
                     albedo: glm::dvec3(0.2, 0.3, 1.0),
-                    roughness: 0.1,
-                    metallic: 0.0,
-                    ..Default::default()
+                    fuzz: 0.1,
                 },
             ],
+            meshes: vec![],
+            background: Background::Sky,
+            bvh: Bvh::empty(),
+            primitives: vec![],
+            lights: vec![],
+            punctual_lights: vec![],
+        };
+        scene.build_bvh();
+        scene
+    }
+
+    // No spheres, meshes, materials or lights, for scenes built up from a file
+    pub fn empty() -> Scene {
+        Scene {
+            spheres: vec![],
+            materials: vec![],
+            meshes: vec![],
+            background: Background::Sky,
+            bvh: Bvh::empty(),
+            primitives: vec![],
+            lights: vec![],
+            punctual_lights: vec![],
         }
     }
 
@@ -95,15 +278,203 @@ impl Scene {
         self.materials.push(material);
         self.materials.len() - 1
     }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    // Has to be called again whenever the spheres, meshes or materials are changed, it also
+    // finds the lights
+    pub fn build_bvh(&mut self) {
+        self.build_bvh_with(&BvhOptions::default());
+    }
+
+    pub fn build_bvh_with(&mut self, options: &BvhOptions) {
+        self.primitives = (0..self.spheres.len()).map(Primitive::Sphere).collect();
+        for (mesh_index, mesh) in self.meshes.iter().enumerate() {
+            self.primitives
+                .extend((0..mesh.triangle_count()).map(|i| Primitive::Triangle(mesh_index, i)));
+        }
+
+        let boxes: Vec<Aabb> = self
+            .primitives
+            .iter()
+            .map(|primitive| match *primitive {
+                Primitive::Sphere(index) => self.spheres[index].bounding_box(),
+                Primitive::Triangle(mesh, triangle) => self.meshes[mesh].bounding_box(triangle),
+            })
+            .collect();
+        self.bvh = Bvh::new(&boxes, options);
+
+        self.lights = (0..self.primitives.len())
+            .filter(|index| {
+                let emitted = self.materials[self.material_index(*index)].emitted();
+                emitted.x > 0.0 || emitted.y > 0.0 || emitted.z > 0.0
+            })
+            .collect();
+    }
+
+    fn material_index(&self, primitive: usize) -> usize {
+        match self.primitives[primitive] {
+            Primitive::Sphere(index) => self.spheres[index].material_index(),
+            Primitive::Triangle(mesh, _) => self.meshes[mesh].material_index(),
+        }
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+
+    // Solid angle density `sample_light` picks the direction of the ray with, when the ray hits
+    // `primitive` at t. Zero when the primitive is not a light.
+    pub fn light_pdf(&self, ray: &Ray, primitive: usize, t: f64) -> f64 {
+        // Lights are sorted, they are collected in the order of the primitives
+        if self.lights.binary_search(&primitive).is_err() {
+            return 0.0;
+        }
+        let point = ray.at(t);
+        let pdf = match self.primitives[primitive] {
+            Primitive::Sphere(index) => {
+                self.spheres[index].direction_pdf(ray.origin(), &point, ray.time())
+            }
+            Primitive::Triangle(mesh, triangle) => {
+                self.triangle_pdf(mesh, triangle, ray.origin(), &point)
+            }
+        };
+        pdf / self.lights.len() as f64
+    }
+
+    // Points spread evenly over the triangle as seen from `position`
+    fn triangle_pdf(&self, mesh: usize, triangle: usize, position: &Vec3, point: &Vec3) -> f64 {
+        let mesh = &self.meshes[mesh];
+        let to_point = *point - *position;
+        let distance_squared = glm::dot(to_point, to_point);
+        let cosine = glm::dot(mesh.geometric_normal(triangle), glm::normalize(to_point)).abs();
+        if distance_squared == 0.0 || cosine < 1e-8 {
+            return 0.0;
+        }
+        distance_squared / (cosine * mesh.area(triangle))
+    }
+
+    // Picks one of the lights evenly and a direction towards it from `position`. None without
+    // lights, or when the light can't be seen from the position.
+    pub fn sample_light(
+        &self,
+        position: &Vec3,
+        time: f64,
+        sampler: &mut Sampler,
+    ) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let count = self.lights.len();
+        let light = self.lights[((random_f64(sampler) * count as f64) as usize).min(count - 1)];
+
+        let (direction, distance, pdf) = match self.primitives[light] {
+            Primitive::Sphere(index) => {
+                self.spheres[index].sample_direction(position, time, sampler)?
+            }
+            Primitive::Triangle(mesh, triangle) => {
+                let point = self.meshes[mesh].sample_point(triangle, sampler);
+                let to_point = point - *position;
+                let distance = glm::length(to_point);
+                let pdf = self.triangle_pdf(mesh, triangle, position, &point);
+                if distance == 0.0 || pdf == 0.0 {
+                    return None;
+                }
+                (to_point / distance, distance, pdf)
+            }
+        };
+
+        Some(LightSample {
+            direction,
+            distance,
+            emitted: self.materials[self.material_index(light)].emitted(),
+            pdf: pdf / count as f64,
+        })
+    }
+
+    // Finds the closest sphere or triangle hit by the ray
+    pub fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // Hits are only ever closer than the previous one, so the last one is what we return
+        let mut barycentric = (0.0, 0.0);
+        let (primitive, t) = self.bvh.hit(
+            ray,
+            t_min,
+            t_max,
+            &mut |index, t_min, t_max| match self.primitives[index] {
+                Primitive::Sphere(sphere) => self.spheres[sphere].hit(ray, t_min, t_max),
+                Primitive::Triangle(mesh, triangle) => {
+                    let (t, b1, b2) = self.meshes[mesh].hit(triangle, ray, t_min, t_max)?;
+                    barycentric = (b1, b2);
+                    Some(t)
+                }
+            },
+        )?;
+
+        Some(Intersection {
+            primitive,
+            t,
+            barycentric,
+        })
+    }
+
+    // Fills in the surface at an intersection found with `intersect`, the normal faces the ray
+    pub(crate) fn hit_payload(&self, ray: &Ray, hit: &Intersection) -> HitPayload {
+        let mut rec = HitPayload {
+            hit_distance: hit.t,
+            world_position: ray.at(hit.t),
+            object_index: hit.primitive as i32,
+            ..Default::default()
+        };
+
+        let (outward_normal, shading_normal) = match self.primitives[hit.primitive] {
+            Primitive::Sphere(index) => {
+                let sphere = &self.spheres[index];
+                let normal = (rec.world_position - sphere.center(ray.time())) / sphere.radius();
+                rec.material_index = sphere.material_index();
+                rec.uv = sphere_uv(&normal);
+                (normal, normal)
+            }
+            Primitive::Triangle(mesh, triangle) => {
+                let mesh = &self.meshes[mesh];
+                let (b1, b2) = hit.barycentric;
+                rec.material_index = mesh.material_index();
+                rec.uv = mesh.uv(triangle, b1, b2);
+                rec.color = mesh.color(triangle, b1, b2);
+                (
+                    mesh.geometric_normal(triangle),
+                    mesh.shading_normal(triangle, b1, b2),
+                )
+            }
+        };
+
+        rec.front_face = glm::dot(*ray.direction(), outward_normal) < 0.0;
+        rec.world_normal = if rec.front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+        rec
+    }
+}
+
+// Longitude and latitude of a point on the unit sphere, both in [0, 1]
+fn sphere_uv(normal: &glm::DVec3) -> glm::DVec2 {
+    let theta = (-normal.y).acos();
+    let phi = (-normal.z).atan2(normal.x) + std::f64::consts::PI;
+    glm::dvec2(
+        phi / (2.0 * std::f64::consts::PI),
+        theta / std::f64::consts::PI,
+    )
 }
 
-pub fn hittable_scene() -> Scene {
+pub fn hittable_scene(sampler: &mut Sampler) -> Scene {
     let mut world = Scene::new();
 
-    let ground_material = world.add_material(Material {
-        albedo: glm::dvec3(0.5, 0.5, 0.5),
-        roughness: 1.0,
-        ..Default::default()
+    let ground_material = world.add_material(Material::Lambertian {
+        albedo: Texture::Solid(glm::dvec3(0.5, 0.5, 0.5)),
     });
     world.spheres.push(Sphere::new(
         glm::dvec3(0.0, -1000.0, 0.0),
@@ -113,73 +484,144 @@ pub fn hittable_scene() -> Scene {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = random_f64();
+            let choose_mat = random_f64(sampler);
             let center = glm::dvec3(
-                a as f64 + 0.9 * random_f64(),
+                a as f64 + 0.9 * random_f64(sampler),
                 0.2,
-                b as f64 + 0.9 * random_f64(),
+                b as f64 + 0.9 * random_f64(sampler),
             );
 
             if glm::length(center - glm::dvec3(4.0, 0.2, 0.0)) > 0.9 {
-                let sphere_material = if choose_mat < 0.8 {
-                    // diffuse
-                    let albedo = random_color() * random_color();
-                    world.add_material(Material {
-                        albedo: albedo,
-                        roughness: 1.0,
-                        ..Default::default()
-                    })
+                let sphere = if choose_mat < 0.8 {
+                    // diffuse, bouncing up while the shutter is open
+                    let albedo = random_color(sampler) * random_color(sampler);
+                    let sphere_material = world.add_material(Material::Lambertian {
+                        albedo: Texture::Solid(albedo),
+                    });
+                    let center2 =
+                        center + glm::dvec3(0.0, random_f64_range(sampler, 0.0, 0.5), 0.0);
+                    Sphere::moving(vec![(0.0, center), (1.0, center2)], 0.2, sphere_material)
                 } else if choose_mat < 0.95 {
                     // metal
-                    let albedo = random_color();
-                    let fuzz = random_f64_range(0.0, 0.5);
-                    world.add_material(Material {
-                        albedo: albedo,
-                        roughness: fuzz,
-                        ..Default::default()
-                    })
+                    let albedo = random_color(sampler);
+                    let fuzz = random_f64_range(sampler, 0.0, 0.5);
+                    let sphere_material = world.add_material(Material::Metal { albedo, fuzz });
+                    Sphere::new(center, 0.2, sphere_material)
                 } else {
                     // glass
-                    world.add_material(Material {
-                        glass: true,
+                    let sphere_material = world.add_material(Material::Dielectric {
                         refraction_index: 1.5,
-                        ..Default::default()
-                    })
+                    });
+                    Sphere::new(center, 0.2, sphere_material)
                 };
 
-                world
-                    .spheres
-                    .push(Sphere::new(center, 0.2, sphere_material));
+                world.spheres.push(sphere);
             }
         }
     }
 
-    let material1 = world.add_material(Material {
-        glass: true,
+    let material1 = world.add_material(Material::Dielectric {
         refraction_index: 1.5,
-        ..Default::default()
     });
     world
         .spheres
         .push(Sphere::new(glm::dvec3(0.0, 1.0, 0.0), 1.0, material1));
 
-    let material2 = world.add_material(Material {
-        albedo: glm::dvec3(0.4, 0.2, 0.1),
-        roughness: 1.0,
-        ..Default::default()
+    let material2 = world.add_material(Material::Lambertian {
+        albedo: Texture::Solid(glm::dvec3(0.4, 0.2, 0.1)),
     });
     world
         .spheres
         .push(Sphere::new(glm::dvec3(-4.0, 1.0, 0.0), 1.0, material2));
 
-    let material3 = world.add_material(Material {
+    let material3 = world.add_material(Material::Metal {
         albedo: glm::dvec3(0.7, 0.6, 0.5),
-        roughness: 0.0,
-        ..Default::default()
+        fuzz: 0.0,
     });
     world
         .spheres
         .push(Sphere::new(glm::dvec3(4.0, 1.0, 0.0), 1.0, material3));
 
+    world.build_bvh();
+    world
+}
+
+// The Cornell box from the second book, lit by the light in the ceiling alone. The box is 555
+// units wide, look at it from (278, 278, -800) with a 40 degree field of view.
+pub fn cornell_box() -> Scene {
+    let mut world = Scene::empty();
+    world.background = Background::Solid(glm::dvec3(0.0, 0.0, 0.0));
+
+    let red = world.add_material(Material::Lambertian {
+        albedo: Texture::Solid(glm::dvec3(0.65, 0.05, 0.05)),
+    });
+    let white = world.add_material(Material::Lambertian {
+        albedo: Texture::Solid(glm::dvec3(0.73, 0.73, 0.73)),
+    });
+    let green = world.add_material(Material::Lambertian {
+        albedo: Texture::Solid(glm::dvec3(0.12, 0.45, 0.15)),
+    });
+    let light = world.add_material(Material::DiffuseLight {
+        emit: glm::dvec3(15.0, 15.0, 15.0),
+    });
+
+    let quads = [
+        // Green on the left, red on the right
+        (
+            (555.0, 0.0, 0.0),
+            (0.0, 555.0, 0.0),
+            (0.0, 0.0, 555.0),
+            green,
+        ),
+        ((0.0, 0.0, 0.0), (0.0, 555.0, 0.0), (0.0, 0.0, 555.0), red),
+        // The light, facing down
+        (
+            (343.0, 554.0, 332.0),
+            (-130.0, 0.0, 0.0),
+            (0.0, 0.0, -105.0),
+            light,
+        ),
+        // Floor, ceiling and back wall
+        ((0.0, 0.0, 0.0), (555.0, 0.0, 0.0), (0.0, 0.0, 555.0), white),
+        (
+            (555.0, 555.0, 555.0),
+            (-555.0, 0.0, 0.0),
+            (0.0, 0.0, -555.0),
+            white,
+        ),
+        (
+            (0.0, 0.0, 555.0),
+            (555.0, 0.0, 0.0),
+            (0.0, 555.0, 0.0),
+            white,
+        ),
+    ];
+    for ((cx, cy, cz), (ux, uy, uz), (vx, vy, vz), material) in quads {
+        world.add_mesh(Mesh::quad(
+            glm::dvec3(cx, cy, cz),
+            glm::dvec3(ux, uy, uz),
+            glm::dvec3(vx, vy, vz),
+            material,
+        ));
+    }
+
+    // A glass and an aluminium sphere instead of the two boxes
+    let glass = world.add_material(Material::Dielectric {
+        refraction_index: 1.5,
+    });
+    let aluminium = world.add_material(Material::Metal {
+        albedo: glm::dvec3(0.8, 0.85, 0.88),
+        fuzz: 0.0,
+    });
+    world
+        .spheres
+        .push(Sphere::new(glm::dvec3(190.0, 90.0, 190.0), 90.0, glass));
+    world.spheres.push(Sphere::new(
+        glm::dvec3(370.0, 120.0, 370.0),
+        120.0,
+        aluminium,
+    ));
+
+    world.build_bvh();
     world
 }
@@ -0,0 +1,1246 @@
+// Scenes described in TOML, so they can be changed without recompiling:
+//
+//     [render]
+//     width = 600
+//     height = 400
+//     samples = 100
+//     output = "spheres.png"
+//
+//     [camera]
+//     look_from = [13, 2, 3]
+//     look_at = [0, 0, 0]
+//     fov = 20
+//
+//     [materials.ground]
+//     type = "lambertian"
+//     albedo = [0.5, 0.5, 0.5]
+//
+//     [[spheres]]
+//     center = [0, -1000, 0]
+//     radius = 1000
+//     material = "ground"
+//
+// Lights without a surface go in `[[lights]]`, with a type of point, spot or directional.
+// Without a `background = [0, 0, 0]` at the top rays that hit nothing see the sky, interiors
+// lit by emissive materials want it black.
+//
+// Everything but the camera is optional. Paths in the file are relative to the file itself.
+// Scenes built in code, like the random spheres, can be written out with `save_scene_file` and
+// loaded again to render exactly the same scene.
+
+use std::{collections::BTreeMap, fmt, fs, io, ops::Range, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::{
+    camera::{Camera, Projection},
+    light::PunctualLight,
+    load::{self, relative_path, LoadError},
+    material::Material,
+    mesh::Mesh,
+    scene::{Background, Scene, Sphere},
+    texture::{ImageTexture, Texture},
+    tonemap::ToneMapper,
+    vec3::Vec3,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    // What rays that hit nothing see, the sky gradient when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<Spanned<[f64; 3]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub render: Option<Spanned<RenderSettings>>,
+    pub camera: Spanned<CameraDescription>,
+    // Sorted by name, so files written out diff well
+    #[serde(default)]
+    pub materials: BTreeMap<String, Spanned<MaterialDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spheres: Vec<Spanned<SphereDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quads: Vec<Spanned<QuadDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<Spanned<MeshDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<Spanned<ModelDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<Spanned<LightDescription>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    // Bounces of the path tracer
+    pub max_depth: u32,
+    pub output: String,
+    // In stops, every stop doubles the brightness
+    pub exposure: f64,
+    // Any of the names `ToneMapper::from_str` takes
+    #[serde(with = "tone_mapper_name")]
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 800,
+            samples: 200,
+            max_depth: 50,
+            output: "image.png".to_string(),
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+}
+
+// Tone mappers are written by name
+mod tone_mapper_name {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::tonemap::ToneMapper;
+
+    pub fn serialize<S: Serializer>(
+        tone_mapper: &ToneMapper,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(tone_mapper)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ToneMapper, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    #[serde(default = "default_projection")]
+    pub projection: Spanned<String>,
+    pub look_from: [f64; 3],
+    pub look_at: [f64; 3],
+    #[serde(default = "default_up")]
+    pub up: [f64; 3],
+    // Vertical field of view in degrees
+    #[serde(default = "default_fov")]
+    pub fov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    // Defaults to the distance to look_at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f64>,
+    // Open and close time, moving spheres blur over it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutter: Option<[f64; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion: Option<CameraMotionDescription>,
+    // Distance between the eyes of stereo pairs, in scene units. Defaults to 6.4cm in meters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpupillary_distance: Option<f64>,
+    // Where the views of the two eyes line up, defaults to the focus distance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zero_parallax_distance: Option<f64>,
+}
+
+// Where the camera has moved to when the shutter closes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraMotionDescription {
+    pub look_from: [f64; 3],
+    pub look_at: [f64; 3],
+}
+
+fn default_projection() -> Spanned<String> {
+    unspanned("perspective".to_string())
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_fov() -> f64 {
+    90.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    // A color, an image or the colors of the mesh vertices
+    Lambertian {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        albedo: Option<[f64; 3]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        texture: Option<String>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        vertex_colors: bool,
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: f64,
+    },
+    DiffuseLight {
+        emit: [f64; 3],
+    },
+    Pbr {
+        base_color: [f64; 3],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        base_color_texture: Option<String>,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(default = "default_roughness")]
+        roughness: f64,
+        #[serde(default)]
+        emission: [f64; 3],
+    },
+}
+
+fn default_roughness() -> f64 {
+    1.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereDescription {
+    // Either a center, or keyframes for spheres that move while the shutter is open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub center: Option<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keyframes: Vec<KeyframeDescription>,
+    pub radius: f64,
+    pub material: Spanned<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyframeDescription {
+    pub time: f64,
+    pub center: [f64; 3],
+}
+
+// A parallelogram spanned by the edges u and v from a corner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuadDescription {
+    pub corner: [f64; 3],
+    pub u: [f64; 3],
+    pub v: [f64; 3],
+    pub material: Spanned<String>,
+}
+
+// Triangles with the vertices written out, normals, uvs and colors are either left out or
+// given for every position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDescription {
+    pub positions: Vec<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub normals: Vec<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uvs: Vec<[f64; 2]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub colors: Vec<[f64; 3]>,
+    pub indices: Vec<[u32; 3]>,
+    pub material: Spanned<String>,
+}
+
+// An OBJ, PLY, STL or glTF file, with its own materials
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDescription {
+    pub path: String,
+}
+
+// Lights without a surface, angles are in degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDescription {
+    // Point and spot lights fade out to nothing at the range, if they have one
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        range: Option<f64>,
+    },
+    // Fades out between the inner and the outer angle from the direction
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        range: Option<f64>,
+        #[serde(default)]
+        inner_angle: f64,
+        #[serde(default = "default_outer_angle")]
+        outer_angle: f64,
+    },
+    // Travelling along the direction, the sun is about half a degree across
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[serde(default)]
+        angular_diameter: f64,
+    },
+}
+
+fn default_outer_angle() -> f64 {
+    45.0
+}
+
+// What a scene file sets up
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(String, io::Error),
+    // Not valid TOML, or not the right fields and types. The message has the location.
+    Toml(String, toml::de::Error),
+    // Well formed, but a value that makes no sense, lines and columns start at 1
+    Invalid {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Load(LoadError),
+    // Scenes with something the format can't describe, like image textures
+    Unsupported(String),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(path, err) => write!(f, "{}: {}", path, err),
+            SceneFileError::Toml(path, err) => write!(f, "{}: {}", path, err),
+            SceneFileError::Invalid {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            SceneFileError::Load(err) => write!(f, "{}", err),
+            SceneFileError::Unsupported(message) => write!(f, "{}", message),
+            SceneFileError::Serialize(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<LoadError> for SceneFileError {
+    fn from(err: LoadError) -> Self {
+        SceneFileError::Load(err)
+    }
+}
+
+pub fn load_scene_file(path: &str) -> Result<SceneFile, SceneFileError> {
+    let source =
+        fs::read_to_string(path).map_err(|err| SceneFileError::Io(path.to_string(), err))?;
+    let description: SceneDescription =
+        toml::from_str(&source).map_err(|err| SceneFileError::Toml(path.to_string(), err))?;
+    Builder {
+        path,
+        source: &source,
+    }
+    .build(&description)
+}
+
+// Turns a description into a scene, checking the values along the way
+struct Builder<'a> {
+    path: &'a str,
+    source: &'a str,
+}
+
+impl Builder<'_> {
+    // Points at the start of the span, spans start at 0
+    fn invalid(&self, span: Range<usize>, message: impl Into<String>) -> SceneFileError {
+        let before = &self.source[..span.start.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        SceneFileError::Invalid {
+            path: self.path.to_string(),
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    fn build(&self, description: &SceneDescription) -> Result<SceneFile, SceneFileError> {
+        let settings = match &description.render {
+            Some(render) => {
+                let settings = render.get_ref().clone();
+                if settings.width < 2 || settings.height < 2 {
+                    return Err(self.invalid(
+                        render.span(),
+                        "the image width and height have to be at least 2",
+                    ));
+                }
+                if settings.samples == 0 {
+                    return Err(self.invalid(render.span(), "samples must not be 0"));
+                }
+                if !settings.exposure.is_finite() {
+                    return Err(self.invalid(render.span(), "the exposure has to be a number"));
+                }
+                settings
+            }
+            None => RenderSettings::default(),
+        };
+
+        let mut scene = Scene::empty();
+        if let Some(background) = &description.background {
+            let color = *background.get_ref();
+            if !color.iter().all(|c| *c >= 0.0) {
+                return Err(self.invalid(background.span(), "the background must not be negative"));
+            }
+            scene.background = Background::Solid(vec3(color));
+        }
+        let mut materials = BTreeMap::new();
+        let mut textures = BTreeMap::new();
+        for (name, material) in &description.materials {
+            let material = self.material(material, &mut textures)?;
+            materials.insert(name.as_str(), scene.add_material(material));
+        }
+        let material_index = |name: &Spanned<String>| {
+            materials
+                .get(name.get_ref().as_str())
+                .copied()
+                .ok_or_else(|| {
+                    let defined: Vec<&str> = materials.keys().copied().collect();
+                    self.invalid(
+                        name.span(),
+                        format!(
+                            "unknown material '{}', the materials are: {}",
+                            name.get_ref(),
+                            defined.join(", ")
+                        ),
+                    )
+                })
+        };
+
+        for sphere in &description.spheres {
+            let span = sphere.span();
+            let sphere = sphere.get_ref();
+            if !positive(sphere.radius) {
+                return Err(self.invalid(span, "the radius has to be greater than 0"));
+            }
+            let material = material_index(&sphere.material)?;
+            let sphere = match (sphere.center, sphere.keyframes.as_slice()) {
+                (Some(center), []) => Sphere::new(vec3(center), sphere.radius, material),
+                (None, keyframes) if !keyframes.is_empty() => Sphere::moving(
+                    keyframes
+                        .iter()
+                        .map(|keyframe| (keyframe.time, vec3(keyframe.center)))
+                        .collect(),
+                    sphere.radius,
+                    material,
+                ),
+                _ => {
+                    return Err(
+                        self.invalid(span, "spheres need either a center or keyframes, not both")
+                    )
+                }
+            };
+            scene.spheres.push(sphere);
+        }
+
+        for quad in &description.quads {
+            let span = quad.span();
+            let quad = quad.get_ref();
+            let (corner, u, v) = (vec3(quad.corner), vec3(quad.u), vec3(quad.v));
+            if glm::length(glm::cross(u, v)) == 0.0 {
+                return Err(self.invalid(span, "the edges u and v must not be parallel"));
+            }
+            scene.add_mesh(Mesh::quad(corner, u, v, material_index(&quad.material)?));
+        }
+
+        for light in &description.lights {
+            scene.punctual_lights.push(self.light(light)?);
+        }
+
+        for mesh in &description.meshes {
+            let span = mesh.span();
+            let mesh = mesh.get_ref();
+            let count = mesh.positions.len();
+            if mesh.indices.is_empty() {
+                return Err(self.invalid(span, "meshes need at least one triangle"));
+            }
+            if [mesh.normals.len(), mesh.uvs.len(), mesh.colors.len()]
+                .iter()
+                .any(|len| *len != 0 && *len != count)
+            {
+                return Err(self.invalid(
+                    span,
+                    format!(
+                        "normals, uvs and colors have to be left out or given for all {} positions",
+                        count
+                    ),
+                ));
+            }
+            if let Some(index) = mesh
+                .indices
+                .iter()
+                .flatten()
+                .find(|i| **i as usize >= count)
+            {
+                return Err(self.invalid(
+                    span,
+                    format!(
+                        "vertex index {} is out of range, there are {}",
+                        index, count
+                    ),
+                ));
+            }
+            scene.add_mesh(Mesh::new(
+                mesh.positions.iter().copied().map(vec3).collect(),
+                mesh.normals.iter().copied().map(vec3).collect(),
+                mesh.uvs.iter().map(|uv| glm::dvec2(uv[0], uv[1])).collect(),
+                mesh.colors.iter().copied().map(vec3).collect(),
+                mesh.indices.clone(),
+                material_index(&mesh.material)?,
+            ));
+        }
+
+        // Cameras in the models are ignored, the scene file has its own
+        for model in &description.models {
+            load::load_model(&relative_path(self.path, &model.get_ref().path), &mut scene)?;
+        }
+        scene.build_bvh();
+
+        let camera = self.camera(&description.camera, &settings)?;
+        Ok(SceneFile {
+            scene,
+            camera,
+            settings,
+        })
+    }
+
+    fn camera(
+        &self,
+        description: &Spanned<CameraDescription>,
+        settings: &RenderSettings,
+    ) -> Result<Camera, SceneFileError> {
+        let span = description.span();
+        let description = description.get_ref();
+        let projection: Projection = description
+            .projection
+            .get_ref()
+            .parse()
+            .map_err(|err: String| self.invalid(description.projection.span(), err))?;
+
+        let (look_from, look_at) = (vec3(description.look_from), vec3(description.look_at));
+        if look_from == look_at {
+            return Err(self.invalid(span, "look_from and look_at must not be the same point"));
+        }
+        // Fisheyes and panoramas can see all around, the other projections span a flat image
+        // plane that grows with tan(fov / 2)
+        match projection {
+            Projection::Perspective | Projection::Orthographic => {
+                if !positive(description.fov) || description.fov >= 180.0 {
+                    return Err(self.invalid(
+                        span,
+                        format!(
+                            "the field of view of {} cameras has to be between 0 and 180",
+                            projection
+                        ),
+                    ));
+                }
+            }
+            _ => {
+                if !positive(description.fov) || description.fov > 360.0 {
+                    return Err(self.invalid(span, "the field of view has to be between 0 and 360"));
+                }
+            }
+        }
+        let up = vec3(description.up);
+        if glm::length(glm::cross(up, look_at - look_from)) == 0.0 {
+            return Err(self.invalid(
+                span,
+                "up must not be zero or parallel to the direction from look_from to look_at",
+            ));
+        }
+        if description.aperture.is_nan() || description.aperture < 0.0 {
+            return Err(self.invalid(span, "the aperture must not be negative"));
+        }
+        let focus_distance = description
+            .focus_distance
+            .unwrap_or_else(|| glm::length(look_at - look_from));
+        if !positive(focus_distance) {
+            return Err(self.invalid(span, "the focus distance has to be greater than 0"));
+        }
+        let interpupillary_distance = description.interpupillary_distance.unwrap_or(0.064);
+        if interpupillary_distance.is_nan() || interpupillary_distance < 0.0 {
+            return Err(self.invalid(span, "the interpupillary distance must not be negative"));
+        }
+        let zero_parallax_distance = description.zero_parallax_distance.unwrap_or(focus_distance);
+        if !positive(zero_parallax_distance) {
+            return Err(self.invalid(span, "the zero parallax distance has to be greater than 0"));
+        }
+
+        let mut camera = Camera::new();
+        camera.set_projection(projection);
+        camera.on_resize(settings.width, settings.height);
+        camera.setup(
+            &look_from,
+            &look_at,
+            &up,
+            description.fov,
+            description.aperture,
+            focus_distance,
+        );
+        if let Some([open, close]) = description.shutter {
+            if close < open {
+                return Err(self.invalid(span, "the shutter closes before it opens"));
+            }
+            camera.set_shutter(open, close);
+        }
+        if let Some(motion) = &description.motion {
+            camera.set_motion(Some((vec3(motion.look_from), vec3(motion.look_at))));
+        }
+        camera.set_stereo(interpupillary_distance, zero_parallax_distance);
+        Ok(camera)
+    }
+
+    fn material(
+        &self,
+        description: &Spanned<MaterialDescription>,
+        textures: &mut BTreeMap<String, Arc<ImageTexture>>,
+    ) -> Result<Material, SceneFileError> {
+        let span = description.span();
+        let color = |color: [f64; 3], what: &str| {
+            if color.iter().all(|c| *c >= 0.0) {
+                Ok(vec3(color))
+            } else {
+                Err(self.invalid(span.clone(), format!("{} must not be negative", what)))
+            }
+        };
+        let unit = |value: f64, what: &str| {
+            if (0.0..=1.0).contains(&value) {
+                Ok(value)
+            } else {
+                Err(self.invalid(span.clone(), format!("{} has to be between 0 and 1", what)))
+            }
+        };
+
+        Ok(match description.get_ref() {
+            MaterialDescription::Lambertian {
+                albedo,
+                texture,
+                vertex_colors,
+            } => {
+                let albedo = match (albedo, texture, vertex_colors) {
+                    (Some(albedo), None, false) => Texture::Solid(color(*albedo, "albedo")?),
+                    (None, Some(texture), false) => {
+                        Texture::Image(self.texture(texture, textures)?, glm::dvec3(1.0, 1.0, 1.0))
+                    }
+                    (None, None, true) => Texture::VertexColor,
+                    _ => {
+                        return Err(self.invalid(
+                            span,
+                            "lambertian materials need one of an albedo, a texture or \
+                             vertex_colors = true",
+                        ))
+                    }
+                };
+                Material::Lambertian { albedo }
+            }
+            MaterialDescription::Metal { albedo, fuzz } => Material::Metal {
+                albedo: color(*albedo, "albedo")?,
+                fuzz: unit(*fuzz, "fuzz")?,
+            },
+            MaterialDescription::Dielectric { refraction_index } => {
+                if !positive(*refraction_index) {
+                    return Err(self.invalid(span, "the refraction index has to be greater than 0"));
+                }
+                Material::Dielectric {
+                    refraction_index: *refraction_index,
+                }
+            }
+            MaterialDescription::DiffuseLight { emit } => Material::DiffuseLight {
+                emit: color(*emit, "emit")?,
+            },
+            MaterialDescription::Pbr {
+                base_color,
+                base_color_texture,
+                metallic,
+                roughness,
+                emission,
+            } => Material::Pbr {
+                base_color: color(*base_color, "base_color")?,
+                base_color_texture: match base_color_texture {
+                    Some(texture) => Some(self.texture(texture, textures)?),
+                    None => None,
+                },
+                metallic: unit(*metallic, "metallic")?,
+                roughness: unit(*roughness, "roughness")?,
+                emission: color(*emission, "emission")?,
+            },
+        })
+    }
+
+    fn light(
+        &self,
+        description: &Spanned<LightDescription>,
+    ) -> Result<PunctualLight, SceneFileError> {
+        let span = description.span();
+        let color = |color: [f64; 3], what: &str| {
+            if color.iter().all(|c| *c >= 0.0) {
+                Ok(vec3(color))
+            } else {
+                Err(self.invalid(span.clone(), format!("{} must not be negative", what)))
+            }
+        };
+        let direction = |direction: [f64; 3]| {
+            let direction = vec3(direction);
+            if glm::length(direction) > 0.0 {
+                Ok(glm::normalize(direction))
+            } else {
+                Err(self.invalid(span.clone(), "the direction must not be 0"))
+            }
+        };
+        let range = |range: Option<f64>| match range {
+            Some(range) if range <= 0.0 || !range.is_finite() => {
+                Err(self.invalid(span.clone(), "the range has to be larger than 0"))
+            }
+            range => Ok(range),
+        };
+        let angle = |degrees: f64, what: &str| {
+            if (0.0..=180.0).contains(&degrees) {
+                Ok(degrees.to_radians())
+            } else {
+                Err(self.invalid(
+                    span.clone(),
+                    format!("{} has to be between 0 and 180 degrees", what),
+                ))
+            }
+        };
+
+        Ok(match description.get_ref() {
+            LightDescription::Point {
+                position,
+                intensity,
+                range: point_range,
+            } => PunctualLight::Point {
+                position: vec3(*position),
+                intensity: color(*intensity, "intensity")?,
+                range: range(*point_range)?,
+            },
+            LightDescription::Spot {
+                position,
+                direction: spot_direction,
+                intensity,
+                range: spot_range,
+                inner_angle,
+                outer_angle,
+            } => {
+                if inner_angle > outer_angle {
+                    return Err(self.invalid(
+                        span,
+                        "the inner angle must not be larger than the outer angle",
+                    ));
+                }
+                PunctualLight::Spot {
+                    position: vec3(*position),
+                    direction: direction(*spot_direction)?,
+                    intensity: color(*intensity, "intensity")?,
+                    range: range(*spot_range)?,
+                    inner_angle: angle(*inner_angle, "inner_angle")?,
+                    outer_angle: angle(*outer_angle, "outer_angle")?,
+                }
+            }
+            LightDescription::Directional {
+                direction: sun_direction,
+                irradiance,
+                angular_diameter,
+            } => PunctualLight::Directional {
+                direction: direction(*sun_direction)?,
+                irradiance: color(*irradiance, "irradiance")?,
+                angular_diameter: angle(*angular_diameter, "angular_diameter")?,
+            },
+        })
+    }
+
+    fn texture(
+        &self,
+        file: &str,
+        textures: &mut BTreeMap<String, Arc<ImageTexture>>,
+    ) -> Result<Arc<ImageTexture>, SceneFileError> {
+        let file = relative_path(self.path, file);
+        if let Some(texture) = textures.get(&file) {
+            return Ok(texture.clone());
+        }
+        let texture = Arc::new(
+            ImageTexture::load(&file)
+                .map_err(|err| LoadError::Image(format!("{} (from {})", file, self.path), err))?,
+        );
+        textures.insert(file, texture.clone());
+        Ok(texture)
+    }
+}
+
+// Also false for NaN, which TOML can spell
+fn positive(value: f64) -> bool {
+    value > 0.0
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    glm::dvec3(v[0], v[1], v[2])
+}
+
+// Writes out the scene, camera and settings, loading the file again gives the same render.
+// Materials are named after their index, so they keep it when the file is loaded.
+pub fn save_scene_file(
+    path: &str,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> Result<(), SceneFileError> {
+    let description = SceneDescription::from_scene(scene, camera, settings)?;
+    let text = toml::to_string(&description).map_err(SceneFileError::Serialize)?;
+    fs::write(path, text).map_err(|err| SceneFileError::Io(path.to_string(), err))
+}
+
+impl SceneDescription {
+    // Meshes are written out triangle by triangle, the models they came from are not known.
+    // Image textures can't be written, since they don't know their file either.
+    pub fn from_scene(
+        scene: &Scene,
+        camera: &Camera,
+        settings: &RenderSettings,
+    ) -> Result<SceneDescription, SceneFileError> {
+        let digits = scene.materials.len().saturating_sub(1).to_string().len();
+        let name = |index: usize| unspanned(format!("material{:0digits$}", index));
+
+        let mut materials = BTreeMap::new();
+        for (index, material) in scene.materials.iter().enumerate() {
+            materials.insert(
+                name(index).into_inner(),
+                unspanned(material_description(index, material)?),
+            );
+        }
+
+        let spheres = scene
+            .spheres
+            .iter()
+            .map(|sphere| {
+                let keyframes: Vec<KeyframeDescription> = sphere
+                    .keyframes()
+                    .iter()
+                    .map(|(time, center)| KeyframeDescription {
+                        time: *time,
+                        center: array(*center),
+                    })
+                    .collect();
+                unspanned(SphereDescription {
+                    center: keyframes.is_empty().then(|| array(sphere.center(0.0))),
+                    keyframes,
+                    radius: sphere.radius(),
+                    material: name(sphere.material_index()),
+                })
+            })
+            .collect();
+
+        let meshes = scene
+            .meshes
+            .iter()
+            .map(|mesh| {
+                unspanned(MeshDescription {
+                    positions: mesh.positions().iter().copied().map(array).collect(),
+                    normals: mesh.normals().iter().copied().map(array).collect(),
+                    uvs: mesh.uvs().iter().map(|uv| [uv.x, uv.y]).collect(),
+                    colors: mesh.colors().iter().copied().map(array).collect(),
+                    indices: mesh.indices().to_vec(),
+                    material: name(mesh.material_index()),
+                })
+            })
+            .collect();
+
+        let setup = camera.camera_setup();
+        let (shutter_open, shutter_close) = camera.shutter();
+        let (interpupillary_distance, zero_parallax_distance) = camera.stereo();
+        let camera = CameraDescription {
+            projection: unspanned(setup.projection.to_string()),
+            look_from: array(setup.look_from),
+            look_at: array(setup.look_at),
+            up: array(setup.v_up),
+            fov: setup.v_fov,
+            aperture: setup.aperture,
+            focus_distance: Some(setup.focus_dist),
+            shutter: Some([shutter_open, shutter_close]),
+            motion: camera
+                .motion()
+                .map(|(look_from, look_at)| CameraMotionDescription {
+                    look_from: array(look_from),
+                    look_at: array(look_at),
+                }),
+            interpupillary_distance: Some(interpupillary_distance),
+            zero_parallax_distance: Some(zero_parallax_distance),
+        };
+
+        Ok(SceneDescription {
+            background: match scene.background {
+                Background::Sky => None,
+                Background::Solid(color) => Some(unspanned(array(color))),
+            },
+            render: Some(unspanned(settings.clone())),
+            camera: unspanned(camera),
+            materials,
+            spheres,
+            quads: vec![],
+            meshes,
+            models: vec![],
+            lights: scene
+                .punctual_lights
+                .iter()
+                .map(|light| unspanned(light_description(light)))
+                .collect(),
+        })
+    }
+}
+
+fn material_description(
+    index: usize,
+    material: &Material,
+) -> Result<MaterialDescription, SceneFileError> {
+    let image_texture = || {
+        SceneFileError::Unsupported(format!(
+            "material {} has an image texture, which can't be written to a scene file",
+            index
+        ))
+    };
+
+    Ok(match material {
+        Material::Lambertian { albedo } => match albedo {
+            Texture::Solid(color) => MaterialDescription::Lambertian {
+                albedo: Some(array(*color)),
+                texture: None,
+                vertex_colors: false,
+            },
+            Texture::VertexColor => MaterialDescription::Lambertian {
+                albedo: None,
+                texture: None,
+                vertex_colors: true,
+            },
+            Texture::Image(..) => return Err(image_texture()),
+        },
+        Material::Metal { albedo, fuzz } => MaterialDescription::Metal {
+            albedo: array(*albedo),
+            fuzz: *fuzz,
+        },
+        Material::Dielectric { refraction_index } => MaterialDescription::Dielectric {
+            refraction_index: *refraction_index,
+        },
+        Material::DiffuseLight { emit } => MaterialDescription::DiffuseLight { emit: array(*emit) },
+        Material::Pbr {
+            base_color_texture: Some(_),
+            ..
+        } => return Err(image_texture()),
+        Material::Pbr {
+            base_color,
+            base_color_texture: None,
+            metallic,
+            roughness,
+            emission,
+        } => MaterialDescription::Pbr {
+            base_color: array(*base_color),
+            base_color_texture: None,
+            metallic: *metallic,
+            roughness: *roughness,
+            emission: array(*emission),
+        },
+    })
+}
+
+fn light_description(light: &PunctualLight) -> LightDescription {
+    match light {
+        PunctualLight::Point {
+            position,
+            intensity,
+            range,
+        } => LightDescription::Point {
+            position: array(*position),
+            intensity: array(*intensity),
+            range: *range,
+        },
+        PunctualLight::Spot {
+            position,
+            direction,
+            intensity,
+            range,
+            inner_angle,
+            outer_angle,
+        } => LightDescription::Spot {
+            position: array(*position),
+            direction: array(*direction),
+            intensity: array(*intensity),
+            range: *range,
+            inner_angle: inner_angle.to_degrees(),
+            outer_angle: outer_angle.to_degrees(),
+        },
+        PunctualLight::Directional {
+            direction,
+            irradiance,
+            angular_diameter,
+        } => LightDescription::Directional {
+            direction: array(*direction),
+            irradiance: array(*irradiance),
+            angular_diameter: angular_diameter.to_degrees(),
+        },
+    }
+}
+
+// Values written out have no place in a file yet
+fn unspanned<T>(value: T) -> Spanned<T> {
+    Spanned::new(0..0, value)
+}
+
+fn array(v: Vec3) -> [f64; 3] {
+    [v.x, v.y, v.z]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "[camera]\nlook_from = [0, 0, 0]\nlook_at = [0, 0, -1]\n";
+
+    fn build(source: &str) -> Result<SceneFile, SceneFileError> {
+        let description: SceneDescription = toml::from_str(source).unwrap();
+        Builder {
+            path: "test.toml",
+            source,
+        }
+        .build(&description)
+    }
+
+    fn location(source: &str) -> (usize, usize) {
+        match build(source) {
+            Err(SceneFileError::Invalid { line, column, .. }) => (line, column),
+            Err(err) => panic!("expected an invalid value, got '{}'", err),
+            Ok(_) => panic!("expected an invalid value"),
+        }
+    }
+
+    #[test]
+    fn bundled_scenes_load() {
+        for path in ["scenes/weekend.toml", "scenes/cornell.toml"] {
+            if let Err(err) = load_scene_file(path) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_materials_point_at_the_name() {
+        let source = format!(
+            "{}\n[materials.red]\ntype = \"lambertian\"\nalbedo = [1, 0, 0]\n\n\
+             [[spheres]]\ncenter = [0, 0, -1]\nradius = 0.5\nmaterial = \"blue\"\n",
+            CAMERA
+        );
+        assert_eq!(location(&source), (12, 12));
+    }
+
+    #[test]
+    fn invalid_tables_point_at_their_header() {
+        let source = format!(
+            "{}\n[materials.red]\ntype = \"lambertian\"\nalbedo = [1, 0, 0]\n\n\
+             [[spheres]]\ncenter = [0, 0, -1]\nradius = 0.5\nmaterial = \"red\"\n\n\
+             [[spheres]]\ncenter = [0, 1, -1]\nradius = 0\nmaterial = \"red\"\n",
+            CAMERA
+        );
+        assert_eq!(location(&source), (14, 1));
+
+        let source = format!(
+            "{}\n[materials.red]\ntype = \"metal\"\nalbedo = [1, -1, 0]\n",
+            CAMERA
+        );
+        assert_eq!(location(&source), (5, 1));
+
+        let source = format!("[render]\nwidth = 1\n\n{}", CAMERA);
+        assert_eq!(location(&source), (1, 1));
+
+        let source = "\n[camera]\nlook_from = [0, 0, 0]\nlook_at = [0, 0, 0]\n";
+        assert_eq!(location(source), (2, 1));
+    }
+
+    #[test]
+    fn cameras_need_a_usable_fov_and_up() {
+        let camera = "\n[camera]\nlook_from = [0, 0, 0]\nlook_at = [0, 0, -1]\n";
+        let source = format!("{}fov = 180\n", camera);
+        assert_eq!(location(&source), (2, 1));
+        let source = format!("{}projection = \"orthographic\"\nfov = 200\n", camera);
+        assert_eq!(location(&source), (2, 1));
+        let source = format!("{}projection = \"equidistant\"\nfov = 200\n", camera);
+        assert!(build(&source).is_ok());
+
+        let source = format!("{}up = [0, 0, 0]\n", camera);
+        assert_eq!(location(&source), (2, 1));
+        let source = format!("{}up = [0, 0, 2]\n", camera);
+        assert_eq!(location(&source), (2, 1));
+    }
+
+    #[test]
+    fn invalid_lights_point_at_their_header() {
+        let source = format!(
+            "{}\n[[lights]]\ntype = \"spot\"\nposition = [0, 1, 0]\ndirection = [0, -1, 0]\n\
+             intensity = [10, 10, 10]\ninner_angle = 50\nouter_angle = 40\n",
+            CAMERA
+        );
+        assert_eq!(location(&source), (5, 1));
+    }
+
+    // Floats may come back an ulp off after going through degrees and normalizing
+    fn assert_close(a: &toml::Value, b: &toml::Value, path: &str) {
+        match (a, b) {
+            (toml::Value::Float(a), toml::Value::Float(b)) => {
+                assert!(
+                    (a - b).abs() <= 1e-9 * a.abs().max(1.0),
+                    "{}: {} != {}",
+                    path,
+                    a,
+                    b
+                )
+            }
+            (toml::Value::Array(a), toml::Value::Array(b)) => {
+                assert_eq!(a.len(), b.len(), "{}", path);
+                for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                    assert_close(a, b, &format!("{}[{}]", path, index));
+                }
+            }
+            (toml::Value::Table(a), toml::Value::Table(b)) => {
+                assert_eq!(
+                    a.keys().collect::<Vec<_>>(),
+                    b.keys().collect::<Vec<_>>(),
+                    "{}",
+                    path
+                );
+                for (key, a) in a {
+                    assert_close(a, &b[key], &format!("{}.{}", path, key));
+                }
+            }
+            (a, b) => assert_eq!(a, b, "{}", path),
+        }
+    }
+
+    fn round_trip_scene() -> (Scene, Camera, RenderSettings) {
+        let mut scene = crate::scene::cornell_box();
+        let pbr = scene.add_material(Material::Pbr {
+            base_color: glm::dvec3(0.9, 0.5, 0.1),
+            base_color_texture: None,
+            metallic: 0.3,
+            roughness: 0.4,
+            emission: glm::dvec3(0.0, 0.0, 0.0),
+        });
+        scene.spheres.push(Sphere::moving(
+            vec![
+                (0.0, glm::dvec3(100.0, 50.0, 100.0)),
+                (1.0, glm::dvec3(100.0, 80.0, 100.0)),
+            ],
+            50.0,
+            pbr,
+        ));
+        scene.punctual_lights = vec![
+            PunctualLight::Point {
+                position: glm::dvec3(278.0, 500.0, 278.0),
+                intensity: glm::dvec3(1000.0, 900.0, 800.0),
+                range: Some(600.0),
+            },
+            PunctualLight::Spot {
+                position: glm::dvec3(100.0, 500.0, 100.0),
+                direction: glm::normalize(glm::dvec3(1.0, -3.0, 1.0)),
+                intensity: glm::dvec3(500.0, 500.0, 500.0),
+                range: None,
+                inner_angle: 20f64.to_radians(),
+                outer_angle: 35f64.to_radians(),
+            },
+            PunctualLight::Directional {
+                direction: glm::normalize(glm::dvec3(-1.0, -2.0, 0.5)),
+                irradiance: glm::dvec3(2.0, 2.0, 2.0),
+                angular_diameter: 0.5f64.to_radians(),
+            },
+        ];
+        scene.background = Background::Solid(glm::dvec3(0.1, 0.2, 0.3));
+
+        let mut camera = Camera::new();
+        camera.setup(
+            &glm::dvec3(278.0, 278.0, -800.0),
+            &glm::dvec3(278.0, 278.0, 0.0),
+            &glm::dvec3(0.0, 1.0, 0.0),
+            40.0,
+            2.0,
+            750.0,
+        );
+        camera.set_shutter(0.0, 1.0);
+        camera.set_motion(Some((
+            glm::dvec3(300.0, 278.0, -800.0),
+            glm::dvec3(278.0, 278.0, 0.0),
+        )));
+        camera.set_stereo(6.4, 800.0);
+
+        let settings = RenderSettings {
+            width: 320,
+            height: 240,
+            samples: 7,
+            exposure: -0.5,
+            tone_mapper: ToneMapper::ReinhardExtended { white_point: 4.0 },
+            ..RenderSettings::default()
+        };
+        (scene, camera, settings)
+    }
+
+    #[test]
+    fn saved_scenes_load_back_the_same() {
+        let (scene, camera, settings) = round_trip_scene();
+        let description = SceneDescription::from_scene(&scene, &camera, &settings).unwrap();
+        let text = toml::to_string(&description).unwrap();
+        let loaded = build(&text).unwrap();
+
+        assert_eq!(loaded.scene.materials, scene.materials);
+        assert_eq!(loaded.scene.meshes.len(), scene.meshes.len());
+        assert_eq!(loaded.scene.spheres.len(), scene.spheres.len());
+        assert_eq!(loaded.settings, settings);
+        assert_eq!(loaded.camera.camera_setup(), camera.camera_setup());
+        assert_eq!(loaded.camera.shutter(), camera.shutter());
+        assert_eq!(loaded.camera.motion(), camera.motion());
+        assert_eq!(loaded.camera.stereo(), camera.stereo());
+
+        let reloaded =
+            SceneDescription::from_scene(&loaded.scene, &loaded.camera, &loaded.settings).unwrap();
+        assert_close(
+            &toml::Value::try_from(&description).unwrap(),
+            &toml::Value::try_from(&reloaded).unwrap(),
+            "scene",
+        );
+    }
+
+    #[test]
+    fn image_textures_can_not_be_saved() {
+        let (mut scene, camera, settings) = round_trip_scene();
+        let texture = Arc::new(ImageTexture::from_srgb8(1, 1, 3, &[255, 0, 0]));
+        scene.add_material(Material::Lambertian {
+            albedo: Texture::Image(texture, glm::dvec3(1.0, 1.0, 1.0)),
+        });
+        assert!(matches!(
+            SceneDescription::from_scene(&scene, &camera, &settings),
+            Err(SceneFileError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,189 @@
+// STL triangle soups, ASCII or binary. Facet normals are ignored, the winding gives the same.
+
+use std::fs;
+
+use crate::{load::LoadError, material::Material, mesh::Mesh, scene::Scene, vec3::Vec3};
+
+pub fn load_stl(path: &str, scene: &mut Scene) -> Result<(), LoadError> {
+    let data = fs::read(path).map_err(|err| LoadError::Io(path.to_string(), err))?;
+
+    // Binary files may start with "solid" too, their size gives them away
+    let binary_size = data.get(80..84).map(|count| {
+        84 + 50 * u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize
+    });
+    let positions = if !data.starts_with(b"solid") || binary_size == Some(data.len()) {
+        read_binary(path, &data)?
+    } else {
+        read_ascii(path, &data)?
+    };
+
+    if positions.is_empty() {
+        return Err(LoadError::parse(path, 1, "no triangles"));
+    }
+    let indices = (0..positions.len() as u32 / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    let material_index = scene.add_material(Material::default());
+    scene.add_mesh(Mesh::new(
+        positions,
+        vec![],
+        vec![],
+        vec![],
+        indices,
+        material_index,
+    ));
+    Ok(())
+}
+
+// An 80 byte header and the triangle count, then 50 bytes for every triangle: the normal,
+// three vertices and two attribute bytes
+fn read_binary(path: &str, data: &[u8]) -> Result<Vec<Vec3>, LoadError> {
+    if data.len() < 84 {
+        return Err(LoadError::binary(
+            path,
+            data.len(),
+            "file too short for the header",
+        ));
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    if data.len() < 84 + 50 * count {
+        return Err(LoadError::binary(
+            path,
+            data.len(),
+            format!("unexpected end of file, expected {} triangles", count),
+        ));
+    }
+
+    let float = |offset: usize| {
+        f32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]) as f64
+    };
+    let mut positions = Vec::with_capacity(3 * count);
+    for triangle in 0..count {
+        let start = 84 + 50 * triangle + 12;
+        for vertex in 0..3 {
+            let offset = start + 12 * vertex;
+            positions.push(glm::dvec3(
+                float(offset),
+                float(offset + 4),
+                float(offset + 8),
+            ));
+        }
+    }
+    Ok(positions)
+}
+
+fn read_ascii(path: &str, data: &[u8]) -> Result<Vec<Vec3>, LoadError> {
+    let text = String::from_utf8_lossy(data);
+    let mut positions = vec![];
+    let mut facet_vertices = 0;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| LoadError::parse(path, line_number, message);
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first().copied() {
+            Some("vertex") => {
+                if tokens.len() != 4 {
+                    return Err(error("expected 3 coordinates after vertex".to_string()));
+                }
+                let mut coordinates = [0.0; 3];
+                for (coordinate, token) in coordinates.iter_mut().zip(&tokens[1..]) {
+                    *coordinate = token
+                        .parse()
+                        .map_err(|_| error(format!("expected a number, found '{}'", token)))?;
+                }
+                positions.push(glm::dvec3(coordinates[0], coordinates[1], coordinates[2]));
+                facet_vertices += 1;
+            }
+            Some("endfacet") => {
+                if facet_vertices != 3 {
+                    return Err(error(format!(
+                        "facets need 3 vertices, this one has {}",
+                        facet_vertices
+                    )));
+                }
+                facet_vertices = 0;
+            }
+            Some("solid") | Some("facet") | Some("outer") | Some("endloop") | Some("endsolid")
+            | None => {}
+            Some(keyword) => return Err(error(format!("unexpected '{}'", keyword))),
+        }
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{error_line, test_file};
+
+    const FACET: &str = "  facet normal 0 0 1\n    outer loop\n      vertex 0 0 0\n      \
+                         vertex 1 0 0\n      vertex 0 1 0\n    endloop\n  endfacet\n";
+
+    fn load(name: &str, contents: impl AsRef<[u8]>) -> Result<Scene, LoadError> {
+        let mut scene = Scene::empty();
+        load_stl(&test_file(name, contents), &mut scene)?;
+        Ok(scene)
+    }
+
+    #[test]
+    fn loads_ascii_and_binary_facets() {
+        let scene = load(
+            "ascii.stl",
+            format!("solid test\n{}{}endsolid test\n", FACET, FACET),
+        );
+        assert_eq!(scene.unwrap().meshes[0].triangle_count(), 2);
+
+        // A binary header may start with "solid" too
+        let mut data = b"solid but binary".to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        for value in [
+            0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            load("binary.stl", data).unwrap().meshes[0].triangle_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn ascii_errors_point_at_the_line() {
+        let facet = FACET.replace("vertex 1 0 0", "vertex 1 x 0");
+        let err = load(
+            "number.stl",
+            format!("solid test\n{}{}endsolid\n", FACET, facet),
+        );
+        assert_eq!(error_line(err.err().unwrap()), 12);
+
+        let facet = FACET.replace("      vertex 0 1 0\n", "");
+        let err = load(
+            "facet.stl",
+            format!("solid test\n{}{}endsolid\n", FACET, facet),
+        );
+        assert_eq!(error_line(err.err().unwrap()), 14);
+
+        let err = load("keyword.stl", format!("solid test\n{}vertx 0 0 0\n", FACET));
+        assert_eq!(error_line(err.err().unwrap()), 9);
+    }
+
+    #[test]
+    fn binary_errors_point_at_the_byte() {
+        let mut data = vec![0; 80];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.resize(84 + 50, 0);
+        match load("truncated.stl", data).err().unwrap() {
+            LoadError::Binary { offset, .. } => assert_eq!(offset, 134),
+            err => panic!("expected a binary error, got '{}'", err),
+        }
+    }
+}
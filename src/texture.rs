@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use crate::{renderer::HitPayload, tonemap::srgb_eotf, vec3::Color3};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Texture {
+    Solid(Color3),
+    // Shared, so materials using the same file only keep one copy around. The color
+    // multiplies every texel, like the Kd factor of an MTL map_Kd.
+    Image(Arc<ImageTexture>, Color3),
+    // The color of the mesh vertices, like scans and PLY files come with
+    VertexColor,
+}
+
+impl Texture {
+    pub fn value(&self, rec: &HitPayload) -> Color3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Image(image, factor) => image.sample(&rec.uv) * *factor,
+            Texture::VertexColor => rec.color,
+        }
+    }
+}
+
+// Linear colors, top row first like the files they come from
+#[derive(Debug, PartialEq)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Color3>,
+}
+
+impl ImageTexture {
+    // 8 bit images are taken to be sRGB encoded
+    pub fn load(path: &str) -> Result<ImageTexture, image::ImageError> {
+        let image = image::open(path)?.into_rgb8();
+        Ok(ImageTexture::from_srgb8(
+            image.width() as usize,
+            image.height() as usize,
+            3,
+            image.as_raw(),
+        ))
+    }
+
+    // Tightly packed 8 bit sRGB pixels, top row first. Images with less than 3 channels are
+    // grayscale, alpha is ignored.
+    pub fn from_srgb8(width: usize, height: usize, channels: usize, pixels: &[u8]) -> ImageTexture {
+        let texels = pixels
+            .chunks_exact(channels)
+            .map(|pixel| {
+                let channel = |i: usize| srgb_eotf(pixel[i] as f64 / 255.0);
+                if channels < 3 {
+                    Color3::new(channel(0), channel(0), channel(0))
+                } else {
+                    Color3::new(channel(0), channel(1), channel(2))
+                }
+            })
+            .collect();
+
+        ImageTexture {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    // Bilinear filtering, uvs outside [0, 1] repeat the image and v = 0 is the bottom row
+    pub fn sample(&self, uv: &glm::DVec2) -> Color3 {
+        if self.texels.is_empty() {
+            return Color3::new(0.0, 0.0, 0.0);
+        }
+
+        let x = uv.x.rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y = (1.0 - uv.y.rem_euclid(1.0)) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.texels[x + y * self.width]
+        };
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
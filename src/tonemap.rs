@@ -0,0 +1,151 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::vec3::Color3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapper {
+    // Everything above 1 clips. The default for renders that don't pick one.
+    #[default]
+    Clamp,
+    Reinhard,
+    // Reinhard that maps `white_point` to 1 instead of only reaching it at infinity
+    ReinhardExtended { white_point: f64 },
+    // Krzysztof Narkowicz's fit of the ACES filmic curve
+    Aces,
+    // John Hable's filmic curve from Uncharted 2
+    Uncharted2,
+}
+
+impl ToneMapper {
+    pub fn map(&self, color: Color3) -> Color3 {
+        match *self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => map_channels(color, |x| x / (1.0 + x)),
+            ToneMapper::ReinhardExtended { white_point } => {
+                let white_squared = white_point * white_point;
+                map_channels(color, |x| x * (1.0 + x / white_squared) / (1.0 + x))
+            }
+            ToneMapper::Aces => map_channels(color, |x| {
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            ToneMapper::Uncharted2 => {
+                let exposure_bias = 2.0;
+                let white_scale = 1.0 / hable(11.2);
+                map_channels(color, |x| hable(x * exposure_bias) * white_scale)
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = String;
+
+    // Extended Reinhard takes its white point after a colon, like reinhard-extended:4
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let lower = name.to_lowercase();
+        if let Some(white_point) = lower.strip_prefix("reinhard-extended") {
+            let white_point = white_point
+                .strip_prefix(':')
+                .and_then(|white_point| white_point.parse::<f64>().ok())
+                .ok_or_else(|| {
+                    format!("'{}' needs a white point, like reinhard-extended:4", name)
+                })?;
+            if white_point <= 0.0 || !white_point.is_finite() {
+                return Err(format!(
+                    "the white point of '{}' has to be greater than 0",
+                    name
+                ));
+            }
+            return Ok(ToneMapper::ReinhardExtended { white_point });
+        }
+
+        match lower.as_str() {
+            "clamp" | "none" => Ok(ToneMapper::Clamp),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "aces" => Ok(ToneMapper::Aces),
+            "uncharted2" | "hable" => Ok(ToneMapper::Uncharted2),
+            _ => Err(format!("unknown tone mapper '{}'", name)),
+        }
+    }
+}
+
+// The names `from_str` takes
+impl fmt::Display for ToneMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToneMapper::Clamp => write!(f, "clamp"),
+            ToneMapper::Reinhard => write!(f, "reinhard"),
+            ToneMapper::ReinhardExtended { white_point } => {
+                write!(f, "reinhard-extended:{}", white_point)
+            }
+            ToneMapper::Aces => write!(f, "aces"),
+            ToneMapper::Uncharted2 => write!(f, "uncharted2"),
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let a = 0.15; // shoulder strength
+    let b = 0.50; // linear strength
+    let c = 0.10; // linear angle
+    let d = 0.20; // toe strength
+    let e = 0.02; // toe numerator
+    let f = 0.30; // toe denominator
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn map_channels(color: Color3, f: impl Fn(f64) -> f64) -> Color3 {
+    Color3::new(
+        f(color.x.max(0.0)),
+        f(color.y.max(0.0)),
+        f(color.z.max(0.0)),
+    )
+}
+
+// The piecewise sRGB transfer function, from linear to display encoded values
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// The inverse of `srgb_oetf`, for reading 8 bit textures back into linear values
+pub fn srgb_eotf(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Turns linear radiance into display ready sRGB values in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    // In stops, every stop doubles the brightness
+    pub exposure: f64,
+    pub tone_mapper: ToneMapper,
+}
+
+impl DisplayTransform {
+    pub fn apply(&self, color: Color3) -> Color3 {
+        let exposed = color * 2f64.powf(self.exposure);
+        let mapped = self.tone_mapper.map(exposed);
+        Color3::new(
+            srgb_oetf(mapped.x.clamp(0.0, 1.0)),
+            srgb_oetf(mapped.y.clamp(0.0, 1.0)),
+            srgb_oetf(mapped.z.clamp(0.0, 1.0)),
+        )
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+}
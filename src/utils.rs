@@ -1,30 +1,46 @@
-use crate::vec3::Color3;
+use crate::{sampler::Sampler, vec3::Color3};
 
-pub fn random_f64() -> f64 {
-    rand::random::<f64>()
+pub fn random_f64(sampler: &mut Sampler) -> f64 {
+    sampler.next_f64()
 }
 
-pub fn random_f64_range(min: f64, max: f64) -> f64 {
-    min + (max - min) * random_f64()
+// Mixes the values into a single well distributed seed (splitmix64)
+pub fn hash_u64(values: &[u64]) -> u64 {
+    let mut hash: u64 = 0;
+    for value in values {
+        hash = (hash ^ value).wrapping_add(0x9e3779b97f4a7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+    }
+    hash
+}
+
+pub fn random_f64_range(sampler: &mut Sampler, min: f64, max: f64) -> f64 {
+    min + (max - min) * random_f64(sampler)
 }
 
-pub fn _random_vec3() -> glm::DVec3 {
-    glm::dvec3(random_f64(), random_f64(), random_f64())
+pub fn _random_vec3(sampler: &mut Sampler) -> glm::DVec3 {
+    glm::dvec3(
+        random_f64(sampler),
+        random_f64(sampler),
+        random_f64(sampler),
+    )
 }
 
-pub fn random_vec3_range(min: f64, max: f64) -> glm::DVec3 {
+pub fn random_vec3_range(sampler: &mut Sampler, min: f64, max: f64) -> glm::DVec3 {
     glm::dvec3(
-        random_f64_range(min, max),
-        random_f64_range(min, max),
-        random_f64_range(min, max),
+        random_f64_range(sampler, min, max),
+        random_f64_range(sampler, min, max),
+        random_f64_range(sampler, min, max),
     )
 }
 
-pub fn _random_unit_vec3() -> glm::DVec3 {
-    glm::normalize(random_in_unit_sphere())
+pub fn random_unit_vec3(sampler: &mut Sampler) -> glm::DVec3 {
+    glm::normalize(random_in_unit_sphere(sampler))
 }
 
-pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
+pub fn _clamp(x: f64, min: f64, max: f64) -> f64 {
     if x < min {
         min
     } else if x > max {
@@ -34,9 +50,9 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     }
 }
 
-pub fn random_in_unit_sphere() -> glm::DVec3 {
+pub fn random_in_unit_sphere(sampler: &mut Sampler) -> glm::DVec3 {
     loop {
-        let p = random_vec3_range(-1.0, 1.0);
+        let p = random_vec3_range(sampler, -1.0, 1.0);
         if glm::length(p).powf(1.0) >= 1.0 {
             continue;
         }
@@ -44,22 +60,48 @@ pub fn random_in_unit_sphere() -> glm::DVec3 {
     }
 }
 
+pub fn random_in_unit_disk(sampler: &mut Sampler) -> glm::DVec3 {
+    loop {
+        let p = glm::dvec3(
+            random_f64_range(sampler, -1.0, 1.0),
+            random_f64_range(sampler, -1.0, 1.0),
+            0.0,
+        );
+        if glm::dot(p, p) < 1.0 {
+            return p;
+        }
+    }
+}
+
+// Spread evenly over the directions around the unit axis, within the angle whose cosine is
+// 1 - one_minus_cos
+pub fn random_in_cone(sampler: &mut Sampler, axis: &glm::DVec3, one_minus_cos: f64) -> glm::DVec3 {
+    let phi = 2.0 * std::f64::consts::PI * random_f64(sampler);
+    let z = 1.0 - random_f64(sampler) * one_minus_cos;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+    let a = if axis.x.abs() > 0.9 {
+        glm::dvec3(0.0, 1.0, 0.0)
+    } else {
+        glm::dvec3(1.0, 0.0, 0.0)
+    };
+    let v = glm::normalize(glm::cross(*axis, a));
+    let u = glm::cross(*axis, v);
+    u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + *axis * z
+}
+
 pub fn near_zero(v: &glm::DVec3) -> bool {
     let s = 1e-8;
     (v.x.abs() < s) && (v.y.abs() < s) && (v.z.abs() < s)
 }
 
-pub fn some_kind_of_gamma(v: &glm::DVec4) -> glm::DVec4 {
-    glm::dvec4(v.x.sqrt(), v.y.sqrt(), v.z.sqrt(), v.w)
-}
-
 // this is used for better lambertian
-pub fn random_in_hemisphere(normal: &glm::DVec3) -> glm::DVec3 {
-    let in_unit_sphere = random_in_unit_sphere();
+pub fn _random_in_hemisphere(sampler: &mut Sampler, normal: &glm::DVec3) -> glm::DVec3 {
+    let in_unit_sphere = random_in_unit_sphere(sampler);
     if glm::dot(in_unit_sphere, *normal) > 0.0 {
-        return in_unit_sphere;
+        in_unit_sphere
     } else {
-        return -in_unit_sphere;
+        -in_unit_sphere
     }
 }
 
@@ -71,6 +113,10 @@ pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
 
-pub fn random_color() -> Color3 {
-    Color3::new(random_f64(), random_f64(), random_f64())
+pub fn random_color(sampler: &mut Sampler) -> Color3 {
+    Color3::new(
+        random_f64(sampler),
+        random_f64(sampler),
+        random_f64(sampler),
+    )
 }